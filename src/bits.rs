@@ -0,0 +1,503 @@
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+const WORD_BITS: usize = 64;
+
+/// A growable sequence of bits packed 64 to a word, most significant bit first, so that any run
+/// of up to 64 bits can be read with a couple of shifts. Bits are given and returned as `u8` 0 or
+/// 1, as they were when strands were stored one bit per byte.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct BitVec {
+    words: Vec<u64>,
+    len: usize,
+}
+
+/// A borrowed run of bits within a `BitVec`.
+#[derive(Clone, Copy)]
+pub struct BitSlice<'a> {
+    words: &'a [u64],
+    start: usize,
+    len: usize,
+}
+
+impl BitVec {
+    pub fn new() -> Self {
+        BitVec::default()
+    }
+
+    pub fn with_capacity(bits: usize) -> Self {
+        BitVec { words: Vec::with_capacity(bits.div_ceil(WORD_BITS)), len: 0 }
+    }
+
+    /// Unpacks `len` bits from bytes holding eight bits each, most significant bit first.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Self {
+        assert!(bytes.len() * 8 >= len, "{} bytes cannot hold {} bits", bytes.len(), len);
+        let mut bits = BitVec::with_capacity(len);
+        for chunk in bytes[..len.div_ceil(8)].chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let count = (len - bits.len).min(WORD_BITS);
+            bits.push_word(u64::from_be_bytes(word) >> (WORD_BITS - count), count);
+        }
+        bits
+    }
+
+    /// See `BitSlice::to_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_bits().to_bytes()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_bits(&self) -> BitSlice<'_> {
+        BitSlice { words: &self.words, start: 0, len: self.len }
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> BitSlice<'_> {
+        self.as_bits().slice(range)
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        self.as_bits().get(index)
+    }
+
+    pub fn set(&mut self, index: usize, bit: u8) {
+        assert!(index < self.len, "bit {} out of range for {} bits", index, self.len);
+        let mask = 1 << (WORD_BITS - 1 - index % WORD_BITS);
+        if bit != 0 {
+            self.words[index / WORD_BITS] |= mask;
+        } else {
+            self.words[index / WORD_BITS] &= !mask;
+        }
+    }
+
+    pub fn flip(&mut self, index: usize) {
+        assert!(index < self.len, "bit {} out of range for {} bits", index, self.len);
+        self.words[index / WORD_BITS] ^= 1 << (WORD_BITS - 1 - index % WORD_BITS);
+    }
+
+    pub fn push(&mut self, bit: u8) {
+        self.push_word((bit != 0) as u64, 1);
+    }
+
+    /// Appends the low `count` bits of `value`, most significant first. `count` is at most 64.
+    pub fn push_word(&mut self, value: u64, count: usize) {
+        assert!(count <= WORD_BITS, "cannot push {} bits from one word", count);
+        if count == 0 {
+            return;
+        }
+        let value = value << (WORD_BITS - count);
+        let offset = self.len % WORD_BITS;
+        if offset == 0 {
+            self.words.push(value);
+        } else {
+            *self.words.last_mut().unwrap() |= value >> offset;
+            if offset + count > WORD_BITS {
+                self.words.push(value << (WORD_BITS - offset));
+            }
+        }
+        self.len += count;
+    }
+
+    /// Appends a run of bits a word at a time.
+    pub fn extend_from_bits(&mut self, bits: BitSlice) {
+        for chunk in bits.chunks(WORD_BITS) {
+            self.push_word(chunk.value(), chunk.len);
+        }
+    }
+
+    /// Shortens the sequence to its first `len` bits.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        self.words.truncate(len.div_ceil(WORD_BITS));
+        let used = len % WORD_BITS;
+        if used != 0 {
+            *self.words.last_mut().unwrap() &= !(u64::MAX >> used);
+        }
+        self.len = len;
+    }
+
+    pub fn insert(&mut self, index: usize, bit: u8) {
+        assert!(index <= self.len, "bit {} out of range for {} bits", index, self.len);
+        let tail = self.slice(index..).to_bit_vec();
+        self.truncate(index);
+        self.push(bit);
+        self.extend_from_bits(tail.as_bits());
+    }
+
+    pub fn remove(&mut self, index: usize) -> u8 {
+        let bit = self.get(index);
+        let tail = self.slice(index + 1..).to_bit_vec();
+        self.truncate(index);
+        self.extend_from_bits(tail.as_bits());
+        bit
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        self.as_bits().iter()
+    }
+
+    /// The bits unpacked one to a byte.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.iter().collect()
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.as_bits().count_ones()
+    }
+
+    /// See `BitSlice::hamming_distance`.
+    pub fn hamming_distance(&self, other: &BitVec) -> usize {
+        self.as_bits().hamming_distance(other.as_bits())
+    }
+}
+
+impl<'a> BitSlice<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        assert!(index < self.len, "bit {} out of range for {} bits", index, self.len);
+        read_word(self.words, self.start + index, 1) as u8
+    }
+
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> BitSlice<'a> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        assert!(
+            start <= end && end <= self.len,
+            "bit range {}..{} out of range for {} bits", start, end, self.len
+        );
+        BitSlice { words: self.words, start: self.start + start, len: end - start }
+    }
+
+    /// Reads the bits as an unsigned integer, most significant bit first. At most 64 bits can be
+    /// read at once.
+    pub fn value(&self) -> u64 {
+        assert!(self.len <= WORD_BITS, "cannot read {} bits as one value", self.len);
+        read_word(self.words, self.start, self.len)
+    }
+
+    /// Consecutive runs of `size` bits; the last may be shorter.
+    pub fn chunks(&self, size: usize) -> impl Iterator<Item = BitSlice<'a>> {
+        assert!(size > 0, "chunk size must be non-zero");
+        let bits = *self;
+        (0..self.len)
+            .step_by(size)
+            .map(move |start| bits.slice(start..(start + size).min(bits.len)))
+    }
+
+    /// Consecutive runs of exactly `size` bits, leaving out any shorter remainder.
+    pub fn chunks_exact(&self, size: usize) -> impl Iterator<Item = BitSlice<'a>> {
+        assert!(size > 0, "chunk size must be non-zero");
+        let bits = *self;
+        (0..self.len / size).map(move |chunk| bits.slice(chunk * size..(chunk + 1) * size))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + 'a {
+        let bits = *self;
+        (0..self.len).map(move |index| bits.get(index))
+    }
+
+    /// Whether the bits begin with `prefix`, compared a word at a time.
+    pub fn starts_with(&self, prefix: BitSlice) -> bool {
+        prefix.len <= self.len && self.slice(..prefix.len) == prefix
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.chunks(WORD_BITS).map(|chunk| chunk.value().count_ones() as usize).sum()
+    }
+
+    /// How many loci differ between the two runs, counted a word at a time. Where one is longer
+    /// than the other, every one of its extra loci counts as a difference.
+    pub fn hamming_distance(&self, other: BitSlice) -> usize {
+        let common = self.len.min(other.len);
+        let differing: usize = self
+            .slice(..common)
+            .chunks(WORD_BITS)
+            .zip(other.slice(..common).chunks(WORD_BITS))
+            .map(|(a, b)| (a.value() ^ b.value()).count_ones() as usize)
+            .sum();
+        differing + self.len.max(other.len) - common
+    }
+
+    /// Packs the bits eight to a byte, most significant bit first; the last byte is padded with
+    /// zeros.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len.div_ceil(8));
+        for chunk in self.chunks(WORD_BITS) {
+            let word = chunk.value() << (WORD_BITS - chunk.len);
+            bytes.extend_from_slice(&word.to_be_bytes()[..chunk.len.div_ceil(8)]);
+        }
+        bytes
+    }
+
+    pub fn to_bit_vec(&self) -> BitVec {
+        let mut bits = BitVec::with_capacity(self.len);
+        bits.extend_from_bits(*self);
+        bits
+    }
+}
+
+/// Reads `len` bits, at most 64, starting `start` bits into `words`.
+fn read_word(words: &[u64], start: usize, len: usize) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+    let (index, offset) = (start / WORD_BITS, start % WORD_BITS);
+    let mut word = words[index] << offset;
+    if offset + len > WORD_BITS {
+        word |= words[index + 1] >> (WORD_BITS - offset);
+    }
+    word >> (WORD_BITS - len)
+}
+
+impl PartialEq for BitSlice<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len
+            && self
+                .chunks(WORD_BITS)
+                .zip(other.chunks(WORD_BITS))
+                .all(|(a, b)| a.value() == b.value())
+    }
+}
+
+impl Eq for BitSlice<'_> {}
+
+impl FromIterator<u8> for BitVec {
+    fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
+        let mut bits = BitVec::new();
+        for bit in iter {
+            bits.push(bit);
+        }
+        bits
+    }
+}
+
+impl From<&[u8]> for BitVec {
+    /// Packs bits stored one to a byte; any non-zero byte is a 1.
+    fn from(bits: &[u8]) -> Self {
+        bits.iter().copied().collect()
+    }
+}
+
+impl From<Vec<u8>> for BitVec {
+    fn from(bits: Vec<u8>) -> Self {
+        BitVec::from(bits.as_slice())
+    }
+}
+
+impl From<BitSlice<'_>> for BitVec {
+    fn from(bits: BitSlice) -> Self {
+        bits.to_bit_vec()
+    }
+}
+
+/// Writes the bits as a string of 0s and 1s.
+impl fmt::Display for BitSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.iter().try_for_each(|bit| f.write_str(if bit == 0 { "0" } else { "1" }))
+    }
+}
+
+impl fmt::Display for BitVec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_bits().fmt(f)
+    }
+}
+
+impl fmt::Debug for BitSlice<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BitSlice({})", self)
+    }
+}
+
+impl fmt::Debug for BitVec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BitVec({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::seeded_rng;
+    use rand::Rng;
+
+    /// Lengths either side of the word boundaries.
+    const LENGTHS: [usize; 12] = [0, 1, 7, 8, 9, 63, 64, 65, 127, 128, 129, 200];
+
+    fn random_bits(rng: &mut impl Rng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| rng.gen_range(0..=1)).collect()
+    }
+
+    /// The bits of `model` packed eight to a byte, most significant bit first.
+    fn pack(model: &[u8]) -> Vec<u8> {
+        model.chunks(8).map(|byte| (value(byte) << (8 - byte.len())) as u8).collect()
+    }
+
+    fn value(model: &[u8]) -> u64 {
+        model.iter().fold(0, |value, &bit| value << 1 | bit as u64)
+    }
+
+    /// Checks every read of `bits` against the model, including that unused bits stay clear.
+    fn assert_matches(bits: &BitVec, model: &[u8]) {
+        assert_eq!(bits.len(), model.len());
+        assert_eq!(bits.to_vec(), model);
+        assert_eq!(bits.count_ones(), model.iter().filter(|&&bit| bit == 1).count());
+        assert_eq!(bits.to_bytes(), pack(model));
+        assert_eq!(*bits, BitVec::from(model));
+    }
+
+    #[test]
+    fn reads_match_a_byte_model() {
+        let mut rng = seeded_rng(1);
+        for len in LENGTHS {
+            let model = random_bits(&mut rng, len);
+            let bits = BitVec::from(model.as_slice());
+            assert_matches(&bits, &model);
+            assert_eq!(BitVec::from_bytes(&pack(&model), len), bits);
+            let text: String = model.iter().map(|bit| bit.to_string()).collect();
+            assert_eq!(bits.to_string(), text);
+            for (index, &bit) in model.iter().enumerate() {
+                assert_eq!(bits.get(index), bit);
+            }
+        }
+    }
+
+    #[test]
+    fn slices_match_a_byte_model() {
+        let mut rng = seeded_rng(2);
+        for len in LENGTHS {
+            let model = random_bits(&mut rng, len);
+            let bits = BitVec::from(model.as_slice());
+            // Whole, empty and word-crossing ranges, then random ones.
+            let mut ranges = vec![(0, len), (len, len), (len / 2, len)];
+            ranges.extend([(60, 70), (63, 65), (1, 65), (64, 128), (65, 129), (3, 131)]);
+            ranges.retain(|&(_, end)| end <= len);
+            for _ in 0..20 {
+                let start = rng.gen_range(0..=len);
+                ranges.push((start, rng.gen_range(start..=len)));
+            }
+
+            for (start, end) in ranges {
+                let slice = bits.slice(start..end);
+                let expected = &model[start..end];
+                assert_eq!(slice.iter().collect::<Vec<_>>(), expected);
+                assert_eq!(slice.to_bit_vec(), BitVec::from(expected));
+                assert_eq!(slice.to_bytes(), pack(expected));
+                assert_eq!(slice.count_ones(), expected.iter().filter(|&&bit| bit == 1).count());
+                if expected.len() <= WORD_BITS {
+                    assert_eq!(slice.value(), value(expected));
+                }
+                for size in [1, 3, 8, 63, 64, 65] {
+                    let chunks: Vec<Vec<u8>> =
+                        slice.chunks(size).map(|chunk| chunk.iter().collect()).collect();
+                    assert_eq!(chunks, expected.chunks(size).collect::<Vec<_>>());
+                    let exact: Vec<Vec<u8>> =
+                        slice.chunks_exact(size).map(|chunk| chunk.iter().collect()).collect();
+                    assert_eq!(exact, expected.chunks_exact(size).collect::<Vec<_>>());
+                }
+
+                assert!(bits.slice(start..).starts_with(slice.slice(..(end - start) / 2)));
+                let other_len = rng.gen_range(0..=len);
+                let other = random_bits(&mut rng, other_len);
+                let other_bits = BitVec::from(other.as_slice());
+                let differing = expected.iter().zip(&other).filter(|(a, b)| a != b).count();
+                let distance = differing + expected.len().max(other.len())
+                    - expected.len().min(other.len());
+                assert_eq!(slice.hamming_distance(other_bits.as_bits()), distance);
+                let is_prefix = other.len() <= expected.len() && expected.starts_with(&other);
+                assert_eq!(slice.starts_with(other_bits.as_bits()), is_prefix);
+                assert_eq!(slice == other_bits.as_bits(), expected == other.as_slice());
+            }
+        }
+    }
+
+    #[test]
+    fn edits_match_a_byte_model() {
+        let mut rng = seeded_rng(3);
+        for len in LENGTHS {
+            let mut model = random_bits(&mut rng, len);
+            let mut bits = BitVec::from(model.as_slice());
+            for _ in 0..300 {
+                match rng.gen_range(0..8) {
+                    0 => {
+                        let bit = rng.gen_range(0..=1);
+                        bits.push(bit);
+                        model.push(bit);
+                    }
+                    1 => {
+                        let count = rng.gen_range(0..=WORD_BITS);
+                        let word = random_bits(&mut rng, count);
+                        bits.push_word(value(&word), count);
+                        model.extend(word);
+                    }
+                    2 if !model.is_empty() => {
+                        let (index, bit) = (rng.gen_range(0..model.len()), rng.gen_range(0..=1));
+                        bits.set(index, bit);
+                        model[index] = bit;
+                    }
+                    3 if !model.is_empty() => {
+                        let index = rng.gen_range(0..model.len());
+                        bits.flip(index);
+                        model[index] ^= 1;
+                    }
+                    4 => {
+                        let (index, bit) = (rng.gen_range(0..=model.len()), rng.gen_range(0..=1));
+                        bits.insert(index, bit);
+                        model.insert(index, bit);
+                    }
+                    5 if !model.is_empty() => {
+                        let index = rng.gen_range(0..model.len());
+                        assert_eq!(bits.remove(index), model.remove(index));
+                    }
+                    6 => {
+                        let len = rng.gen_range(0..=model.len() + 1);
+                        bits.truncate(len);
+                        model.truncate(len);
+                    }
+                    7 => {
+                        let source_len = rng.gen_range(0..150);
+                        let source = BitVec::from(random_bits(&mut rng, source_len));
+                        let start = rng.gen_range(0..=source.len());
+                        let run = source.slice(start..);
+                        bits.extend_from_bits(run);
+                        model.extend(run.iter());
+                    }
+                    _ => {}
+                }
+                assert_matches(&bits, &model);
+            }
+        }
+    }
+
+    #[test]
+    fn value_of_a_full_word() {
+        let bits = BitVec::from_bytes(&[0xFF; 9], 65);
+        assert_eq!(bits.slice(1..).value(), u64::MAX);
+        assert_eq!(bits.slice(..64).value(), u64::MAX);
+        assert_eq!(bits.slice(64..).value(), 1);
+    }
+}
@@ -1,7 +1,17 @@
 use std::time::Duration;
-use crate::genome::{Genome, BITS_PER_PARAMETER, PARAMETERS};
+use crate::bits::{BitSlice, BitVec};
+use crate::envelope::{Envelope, EnvelopeCurve};
+use crate::expression::{Expression, ExpressionModel};
+use crate::genome_schema::{Codon, EFFECTS, NOTES, SCHEMA, TEMPO, TIMBRE};
+use crate::genome::{
+    Genome, BITS_PER_HARMONIC, BITS_PER_PARAMETER, EFFECT_SCOPE_BITS, ENVELOPE_BITS, PAN_BITS,
+    PARAMETERS, TIMBRE_HARMONICS,
+};
+use crate::pitch::{PitchDecoder, PitchMapping};
+use crate::rhythm::{RhythmDecoder, RhythmMapping, DEFAULT_BPM};
 use std::f32::consts::PI;
 
+#[derive(Clone, Debug)]
 pub struct DecodedParameters {
     pub start_time: Duration,
     pub frequency: f32,
@@ -9,71 +19,296 @@ pub struct DecodedParameters {
     pub duration: Duration,
     pub phase: f32,
     pub wave_function: Option<WaveFunction>,
+    pub envelope: Envelope,
+    /// Position in the stereo field, from -1.0 (left) through 0.0 (centre) to 1.0 (right).
+    pub pan: f32,
 }
 
+impl DecodedParameters {
+    /// When the note falls silent: the end of its duration plus its release.
+    pub fn end_time(&self) -> Duration {
+        self.start_time + self.duration + self.envelope.release
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct DecodedGenome {
     pub notes: Vec<DecodedParameters>,
-    pub effects: Vec<Effect>,
+    pub effects: Vec<ScopedEffect>,
+    /// Spectrum played by notes whose wave function is `WaveFunction::Custom`.
+    pub timbre: Timbre,
+    /// Tempo in beats per minute when the notes were placed on a rhythmic grid.
+    pub tempo: Option<f32>,
+}
+
+/// The harmonic spectrum of the custom waveform.
+#[derive(Clone, Debug)]
+pub struct Timbre {
+    pub harmonics: Vec<Harmonic>,
+}
+
+/// One partial of a `Timbre`. The `n`th entry of `Timbre::harmonics` sounds at `n + 1` times the
+/// note's frequency.
+#[derive(Clone, Copy, Debug)]
+pub struct Harmonic {
+    pub amplitude: f32,
+    pub phase: f32,
+}
+
+impl Default for Timbre {
+    /// Ten harmonics with amplitudes falling as 1/n, used when a genome encodes no audible
+    /// spectrum.
+    fn default() -> Self {
+        Timbre {
+            harmonics: (1..=10)
+                .map(|n| Harmonic { amplitude: 1.0 / n as f32, phase: 0.0 })
+                .collect(),
+        }
+    }
 }
 
+/// A decoded effect. The filters carry their cutoff (or centre) frequency in Hz and their Q.
+#[derive(Clone, Debug)]
 pub enum Effect {
-    LowPass(f32),
-    HighPass(f32),
-    Reverb(Duration, f32),
-    Echo(Duration, f32),
+    LowPass(f32, f32),
+    HighPass(f32, f32),
+    BandPass(f32, f32),
+    Notch(f32, f32),
+    /// The original one-pole low-pass of genomes from before format version 6, with its
+    /// smoothing coefficient from 0.0 to just under 2.0.
+    LegacyLowPass(f32),
+    /// The original one-pole high-pass of genomes from before format version 6, with its
+    /// coefficient from 0.0 to just under 2.0.
+    LegacyHighPass(f32),
+    /// The original feedback comb of genomes from before format version 6: its delay, and the
+    /// level each pass around the comb keeps.
+    LegacyReverb(Duration, f32),
+    /// The original single-repeat echo of genomes from before format version 6: its delay, and
+    /// the level of the repeat.
+    LegacyEcho(Duration, f32),
+    /// Room size, damping of the high frequencies in the tail, and wet/dry mix, each from 0.0 to
+    /// 1.0.
+    Reverb(f32, f32, f32),
+    /// Time between repeats, the level each repeat keeps of the one before (below 1.0), the
+    /// high-cut frequency in Hz applied at every repeat, and the number of taps spread evenly
+    /// across the delay time.
+    Echo(Duration, f32, f32, usize),
+    /// Drive, the gain into the waveshaper from 1.0 to 50.0, and wet/dry mix.
+    Distortion(f32, f32),
+    /// Rate of the delay sweep in Hz, its depth, and wet/dry mix.
+    Chorus(f32, Duration, f32),
+    /// Rate of the delay sweep in Hz, its depth, and the feedback around the delay (below 1.0).
+    Flanger(f32, Duration, f32),
+    /// Bit depth the signal is quantised to, and how many samples each quantised value is held
+    /// for.
+    Bitcrusher(u32, usize),
+    /// Threshold in dB below full scale, and the ratio by which levels above it are reduced.
+    Compressor(f32, f32),
+}
+
+/// An effect and the part of the song it is applied to.
+#[derive(Clone, Debug)]
+pub struct ScopedEffect {
+    pub effect: Effect,
+    pub scope: EffectScope,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum EffectScope {
+    /// The whole mix.
+    #[default]
+    Global,
+    /// Only the notes of one wave function, which are mixed on a bus of their own before they
+    /// join the rest.
+    Voice(WaveFunction),
+    /// The whole mix between a start and an end time, fading in and out at either end.
+    Section(Duration, Duration),
+}
+
+/// Choices about how genes are interpreted as music. The default decodes pitches onto a musical
+/// scale, keeps the original free timing and reads note and effect genes as the genome's format
+/// version lays them out; use `PitchMapping::Legacy` to hear old genomes as they originally
+/// sounded (less the clicks), or `RhythmMapping::grid()` to give them a pulse.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodeConfig {
+    pub pitch: PitchMapping,
+    pub rhythm: RhythmMapping,
+    /// The layout of note genes, or `None` for the layout of the genome's format version.
+    pub note_layout: Option<NoteLayout>,
+    /// The layout of effect genes, or `None` for the layout of the genome's format version.
+    pub effect_layout: Option<EffectLayout>,
+    pub expression: ExpressionModel,
+}
+
+/// How many bits of the note chromosome follow each note codon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteLayout {
+    /// The original five parameters. Notes get the default envelope; genomes from files before
+    /// version 4, when notes gained envelope genes, are read this way.
+    Legacy,
+    /// The five parameters followed by `ENVELOPE_BITS` of envelope genes.
+    Extended,
+    /// The extended genes followed by `PAN_BITS` of pan. Notes of the other layouts sit in the
+    /// centre.
+    Stereo,
+}
+
+impl NoteLayout {
+    /// The layout genomes of format `version` were written with.
+    pub fn for_version(version: u16) -> Self {
+        if version < 4 { NoteLayout::Legacy } else { NoteLayout::Extended }
+    }
+
+    fn extension_bits(self) -> usize {
+        match self {
+            NoteLayout::Legacy => 0,
+            NoteLayout::Extended => ENVELOPE_BITS,
+            NoteLayout::Stereo => ENVELOPE_BITS + PAN_BITS,
+        }
+    }
+}
+
+/// How the genes after each effect codon are read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectLayout {
+    /// The layout of files before version 6: the low-pass and high-pass filters carry one gene,
+    /// their one-pole coefficient, and the reverb and echo two each, a delay and a feedback
+    /// level. Every effect applies to the whole mix.
+    Legacy,
+    /// The layout of files of versions 6 and 7: every filter carries a frequency and a Q gene,
+    /// the reverb and echo three genes each. Every effect applies to the whole mix.
+    Unscoped,
+    /// The unscoped genes followed by `EFFECT_SCOPE_BITS` of scope.
+    Scoped,
 }
 
-#[derive(Clone)]
-enum WaveFunction {
+impl EffectLayout {
+    /// The layout genomes of format `version` were written with.
+    pub fn for_version(version: u16) -> Self {
+        match version {
+            ..=5 => EffectLayout::Legacy,
+            6..=7 => EffectLayout::Unscoped,
+            _ => EffectLayout::Scoped,
+        }
+    }
+
+    fn scope_bits(self) -> usize {
+        match self {
+            EffectLayout::Legacy | EffectLayout::Unscoped => 0,
+            EffectLayout::Scoped => EFFECT_SCOPE_BITS,
+        }
+    }
+}
+
+/// Everything needed to turn the parameter genes of a note into a `DecodedParameters`.
+struct NoteDecoder {
+    pitch: PitchDecoder,
+    rhythm: RhythmDecoder,
+    layout: NoteLayout,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WaveFunction {
     Sine,
     Square,
+    Saw,
+    Triangle,
     Custom,
 }
 
 impl DecodedGenome {
     pub fn decode(genome: &Genome) -> Self {
+        Self::decode_with(genome, &DecodeConfig::default())
+    }
+
+    pub fn decode_with(genome: &Genome, config: &DecodeConfig) -> Self {
+        let expression = Expression::new(&config.expression, genome);
+        let express = |position: usize, voice| {
+            expression.express(&genome[position], SCHEMA.chromosomes[position].name, voice)
+        };
+        let tempo = express(TEMPO, 0);
+        let note_decoder = NoteDecoder {
+            pitch: PitchDecoder::new(&config.pitch),
+            rhythm: RhythmDecoder::new(
+                &config.rhythm, bits_to_value(tempo.slice(..tempo.len().min(BITS_PER_PARAMETER)))),
+            layout: config.note_layout.unwrap_or(NoteLayout::for_version(genome.version())),
+        };
         let mut notes = Vec::new();
         let mut effects = Vec::new();
 
-        // Decode the note chromosome.
-        let note_chromosome = genome.notes.get_left_chromosome();
-        let note_codons = vec![
-            (genome.sine_codon.get_left_chromosome(), WaveFunction::Sine),
-            (genome.square_codon.get_left_chromosome(), WaveFunction::Square),
-            (genome.custom_codon.get_left_chromosome(), WaveFunction::Custom),
-        ];
-
-        notes.extend(decode_chromosome(note_chromosome, &note_codons));
+        // Decode the note chromosome, once per expressed voice.
+        for voice in 0..expression.voices() {
+            let note_chromosome = express(NOTES, voice);
+            let codons: Vec<(BitVec, WaveFunction)> = SCHEMA
+                .codons()
+                .filter_map(|(position, codon)| match codon {
+                    Codon::Note(wave_function) => Some((express(position, voice), wave_function)),
+                    Codon::Effect(_) => None,
+                })
+                .collect();
+            let note_codons: Vec<(BitSlice, WaveFunction)> = codons
+                .iter()
+                .map(|(codon, wave_function)| (codon.as_bits(), *wave_function))
+                .collect();
+            notes.extend(decode_chromosome(note_chromosome.as_bits(), &note_codons, &note_decoder));
+        }
 
         // Decode the effect chromosome.
-        let effect_chromosome = genome.effects.get_left_chromosome();
-        let effect_codons = vec![
-            (genome.low_pass_codon.get_left_chromosome(), EffectType::LowPass),
-            (genome.high_pass_codon.get_left_chromosome(), EffectType::HighPass),
-            (genome.reverb_codon.get_left_chromosome(), EffectType::Reverb),
-            (genome.echo_codon.get_left_chromosome(), EffectType::Echo),
-        ];
-        effects.extend(decode_effect_chromosome(effect_chromosome, &effect_codons));
+        let effect_chromosome = express(EFFECTS, 0);
+        let codons: Vec<(BitVec, EffectType)> = SCHEMA
+            .codons()
+            .filter_map(|(position, codon)| match codon {
+                Codon::Effect(effect_type) => Some((express(position, 0), effect_type)),
+                Codon::Note(_) => None,
+            })
+            .collect();
+        let effect_codons: Vec<(BitSlice, EffectType)> = codons
+            .iter()
+            .map(|(codon, effect_type)| (codon.as_bits(), *effect_type))
+            .collect();
+        let song = EffectContext {
+            layout: config.effect_layout.unwrap_or(EffectLayout::for_version(genome.version())),
+            beat: Duration::from_secs_f32(60.0 / note_decoder.rhythm.bpm().unwrap_or(DEFAULT_BPM)),
+            length: notes.iter().map(|note| note.end_time()).max().unwrap_or_default(),
+        };
+        effects.extend(
+            decode_effect_chromosome(effect_chromosome.as_bits(), &effect_codons, &song));
+
+        let timbre = decode_timbre(express(TIMBRE, 0).as_bits());
 
-        DecodedGenome { notes, effects }
+        DecodedGenome { notes, effects, timbre, tempo: note_decoder.rhythm.bpm() }
     }
 }
 
-fn decode_chromosome(chromosome: &[u8], codons: &[(&[u8], WaveFunction)]) -> Vec<DecodedParameters> {
+fn decode_chromosome(
+    chromosome: BitSlice, codons: &[(BitSlice, WaveFunction)], note_decoder: &NoteDecoder
+) -> Vec<DecodedParameters> {
     let mut decoded_params = Vec::new();
     let param_length = PARAMETERS * BITS_PER_PARAMETER;
+    let extended_length = param_length + note_decoder.layout.extension_bits();
     let mut i = 0;
 
     while i < chromosome.len() {
         for (codon, wave_function) in codons {
-            if i + codon.len() <= chromosome.len() && matches_codon(
-                &chromosome[i..i + codon.len()], codon) {
+            // An empty codon would match everywhere; genomes loaded from files older than the
+            // codon's chromosome carry one, and it simply never starts a note.
+            if codon.is_empty() {
+                continue;
+            }
+            if chromosome.slice(i..).starts_with(*codon) {
                 if i + codon.len() + param_length <= chromosome.len() {
                     i += codon.len();
+                    // A note cut short by the end of the chromosome keeps its parameters and
+                    // falls back to the default envelope.
+                    let note_length = if i + extended_length <= chromosome.len() {
+                        extended_length
+                    } else {
+                        param_length
+                    };
                     let params = decode_parameters(
-                        &chromosome[i..i + param_length], Some(wave_function.clone()));
+                        chromosome.slice(i..i + note_length), Some(*wave_function), note_decoder);
                     decoded_params.push(params);
-                    i += param_length;
+                    i += note_length;
                 } else {
                     break;
                 }
@@ -85,13 +320,24 @@ fn decode_chromosome(chromosome: &[u8], codons: &[(&[u8], WaveFunction)]) -> Vec
     decoded_params
 }
 
-fn decode_effect_chromosome(chromosome: &[u8], codons: &[(&[u8], EffectType)]) -> Vec<Effect> {
+/// What effect genes need to know about the song they belong to.
+struct EffectContext {
+    layout: EffectLayout,
+    /// Length of a beat at the song's tempo, for effects synced to it.
+    beat: Duration,
+    /// Length of the song, for effects scoped to a section of it.
+    length: Duration,
+}
+
+fn decode_effect_chromosome(
+    chromosome: BitSlice, codons: &[(BitSlice, EffectType)], song: &EffectContext
+) -> Vec<ScopedEffect> {
     let mut effects = Vec::new();
     let mut i = 0;
 
     while i < chromosome.len() {
         if let Some((effect, effect_size)) = decode_effects(
-            &chromosome[i..], codons) {
+            chromosome.slice(i..), codons, song) {
             effects.push(effect);
             i += effect_size;
         } else {
@@ -102,51 +348,209 @@ fn decode_effect_chromosome(chromosome: &[u8], codons: &[(&[u8], EffectType)]) -
     effects
 }
 
-fn decode_effects(bits: &[u8], codons: &[(&[u8], EffectType)]) -> Option<(Effect, usize)> {
+fn decode_effects(
+    bits: BitSlice, codons: &[(BitSlice, EffectType)], song: &EffectContext
+) -> Option<(ScopedEffect, usize)> {
     for (codon, effect_type) in codons {
         let codon_size = codon.len();
-        if bits.len() >= codon_size && matches_codon(&bits[0..codon_size], codon) {
-            let total_size = match effect_type {
-                EffectType::LowPass => codon_size + BITS_PER_PARAMETER,
-                EffectType::HighPass => codon_size + BITS_PER_PARAMETER,
-                EffectType::Reverb => codon_size + 2 * BITS_PER_PARAMETER,
-                EffectType::Echo => codon_size + 2 * BITS_PER_PARAMETER,
-            };
+        if codon_size == 0 {
+            continue;
+        }
+        if bits.starts_with(*codon) {
+            let scope_start =
+                codon_size + effect_type.parameters(song.layout) * BITS_PER_PARAMETER;
+            let total_size = scope_start + song.layout.scope_bits();
             if bits.len() >= total_size {
+                let parameter = |n: usize| {
+                    let start = codon_size + n * BITS_PER_PARAMETER;
+                    bits.slice(start..start + BITS_PER_PARAMETER)
+                };
+                let legacy = song.layout == EffectLayout::Legacy;
                 let effect_instance = match effect_type {
-                    EffectType::LowPass => Effect::LowPass(bits_to_amplitude(&bits[codon_size..total_size])),
-                    EffectType::HighPass => Effect::HighPass(bits_to_amplitude(&bits[codon_size..total_size])),
-                    EffectType::Reverb => {
-                        let delay = bits_to_duration(&bits[codon_size..codon_size + BITS_PER_PARAMETER]);
-                        let feedback = bits_to_amplitude(&bits[codon_size + BITS_PER_PARAMETER..total_size]);
-                        Effect::Reverb(delay, feedback)
+                    EffectType::LowPass if legacy => {
+                        Effect::LegacyLowPass(bits_to_amplitude(parameter(0)))
+                    }
+                    EffectType::HighPass if legacy => {
+                        Effect::LegacyHighPass(bits_to_amplitude(parameter(0)))
+                    }
+                    EffectType::Reverb if legacy => Effect::LegacyReverb(
+                        bits_to_duration(parameter(0)),
+                        bits_to_amplitude(parameter(1)),
+                    ),
+                    EffectType::Echo if legacy => Effect::LegacyEcho(
+                        bits_to_duration(parameter(0)),
+                        bits_to_amplitude(parameter(1)),
+                    ),
+                    EffectType::LowPass => {
+                        Effect::LowPass(bits_to_cutoff(parameter(0)), bits_to_q(parameter(1)))
                     }
+                    EffectType::HighPass => {
+                        Effect::HighPass(bits_to_cutoff(parameter(0)), bits_to_q(parameter(1)))
+                    }
+                    EffectType::BandPass => {
+                        Effect::BandPass(bits_to_cutoff(parameter(0)), bits_to_q(parameter(1)))
+                    }
+                    EffectType::Notch => {
+                        Effect::Notch(bits_to_cutoff(parameter(0)), bits_to_q(parameter(1)))
+                    }
+                    EffectType::Reverb => Effect::Reverb(
+                        bits_to_fraction(parameter(0)),
+                        bits_to_fraction(parameter(1)),
+                        bits_to_fraction(parameter(2)),
+                    ),
                     EffectType::Echo => {
-                        let delay = bits_to_duration(&bits[codon_size..codon_size + BITS_PER_PARAMETER]);
-                        let feedback = bits_to_amplitude(&bits[codon_size + BITS_PER_PARAMETER..total_size]);
-                        Effect::Echo(delay, feedback)
+                        decode_echo(parameter(0), parameter(1), parameter(2), song.beat)
                     }
+                    EffectType::Distortion => Effect::Distortion(
+                        50f32.powf(bits_to_fraction(parameter(0))),
+                        bits_to_fraction(parameter(1)),
+                    ),
+                    EffectType::Chorus => Effect::Chorus(
+                        0.1 * 50f32.powf(bits_to_fraction(parameter(0))),
+                        Duration::from_secs_f32(0.001 + 0.009 * bits_to_fraction(parameter(1))),
+                        bits_to_fraction(parameter(2)),
+                    ),
+                    EffectType::Flanger => Effect::Flanger(
+                        0.05 * 40f32.powf(bits_to_fraction(parameter(0))),
+                        Duration::from_secs_f32(0.0005 + 0.0045 * bits_to_fraction(parameter(1))),
+                        0.9 * bits_to_fraction(parameter(2)),
+                    ),
+                    EffectType::Bitcrusher => Effect::Bitcrusher(
+                        1 + bits_to_value(parameter(0)) * 15 / 255,
+                        1 + bits_to_value(parameter(1)) as usize * 31 / 255,
+                    ),
+                    EffectType::Compressor => Effect::Compressor(
+                        -40.0 * bits_to_fraction(parameter(0)),
+                        20f32.powf(bits_to_fraction(parameter(1))),
+                    ),
                 };
-                return Some((effect_instance, total_size));
+                let scope = if total_size > scope_start {
+                    decode_scope(bits.slice(scope_start..total_size), song.length)
+                } else {
+                    EffectScope::Global
+                };
+                return Some((ScopedEffect { effect: effect_instance, scope }, total_size));
             }
         }
     }
     None
 }
 
-enum EffectType {
+/// Decodes the scope gene of an effect. Its first two bits choose the scope: half of the values
+/// are global; the rest pick a voice from the next three bits, or a section of the song starting
+/// at the eighth given by the next three bits and lasting the number of eighths given by the last
+/// three, plus one.
+fn decode_scope(bits: BitSlice, length: Duration) -> EffectScope {
+    const VOICES: [WaveFunction; 5] = [
+        WaveFunction::Sine, WaveFunction::Square, WaveFunction::Saw, WaveFunction::Triangle,
+        WaveFunction::Custom,
+    ];
+    match bits_to_value(bits.slice(0..2)) {
+        2 => EffectScope::Voice(VOICES[bits_to_value(bits.slice(2..5)) as usize % VOICES.len()]),
+        3 => {
+            let eighth = length / 8;
+            let start = eighth * bits_to_value(bits.slice(2..5));
+            let end = (start + eighth * (bits_to_value(bits.slice(5..8)) + 1)).min(length);
+            EffectScope::Section(start, end)
+        }
+        _ => EffectScope::Global,
+    }
+}
+
+/// Note lengths, in beats, that a tempo-synced echo chooses between: from a semiquaver to a
+/// minim, with the dotted and triplet values in between.
+const ECHO_DIVISIONS: [f32; 10] = [
+    0.25, 1.0 / 3.0, 0.375, 0.5, 2.0 / 3.0, 0.75, 1.0, 4.0 / 3.0, 1.5, 2.0,
+];
+
+/// Decodes the delay time and feedback genes of an echo and its extension gene. The first bit of
+/// the extension syncs the delay to the tempo, the next two give one to four taps, and the last
+/// five the high-cut.
+fn decode_echo(delay: BitSlice, feedback: BitSlice, extension: BitSlice, beat: Duration) -> Effect {
+    let delay = if extension.get(0) == 1 {
+        let division = bits_to_value(delay) as usize * ECHO_DIVISIONS.len() / 256;
+        beat.mul_f32(ECHO_DIVISIONS[division])
+    } else {
+        // 10 ms to 2 s on a logarithmic scale.
+        Duration::from_secs_f32(0.01 * 200f32.powf(bits_to_fraction(delay)))
+    };
+    // Feedback is held below 1.0 so that the repeats always die away.
+    let feedback = bits_to_fraction(feedback) * 0.9;
+    let taps = bits_to_value(extension.slice(1..3)) as usize + 1;
+    // 1 kHz to 20 kHz on a logarithmic scale.
+    let high_cut = 1000.0 * 20f32.powf(bits_to_value(extension.slice(3..8)) as f32 / 31.0);
+    Effect::Echo(delay, feedback, high_cut, taps)
+}
+
+/// Reads one harmonic per chunk of the timbre chromosome: the first five bits give its amplitude
+/// and the last three its phase in eighths of a cycle. Falls back to the default spectrum if the
+/// chromosome is too short or every amplitude is zero.
+fn decode_timbre(chromosome: BitSlice) -> Timbre {
+    let harmonics: Vec<Harmonic> = chromosome
+        .chunks_exact(BITS_PER_HARMONIC)
+        .take(TIMBRE_HARMONICS)
+        .map(|chunk| Harmonic {
+            amplitude: bits_to_value(chunk.slice(0..5)) as f32 / 31.0,
+            phase: bits_to_value(chunk.slice(5..8)) as f32 * 2.0 * PI / 8.0,
+        })
+        .collect();
+
+    if harmonics.iter().all(|harmonic| harmonic.amplitude == 0.0) {
+        Timbre::default()
+    } else {
+        Timbre { harmonics }
+    }
+}
+
+/// The kinds of effect, each started by its own codon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectType {
     LowPass,
     HighPass,
+    BandPass,
+    Notch,
     Reverb,
     Echo,
+    Distortion,
+    Chorus,
+    Flanger,
+    Bitcrusher,
+    Compressor,
+}
+
+impl EffectType {
+    /// How many `BITS_PER_PARAMETER` genes follow the effect's codon in `layout`.
+    fn parameters(self, layout: EffectLayout) -> usize {
+        match self {
+            EffectType::LowPass | EffectType::HighPass if layout == EffectLayout::Legacy => 1,
+            EffectType::Reverb | EffectType::Echo if layout == EffectLayout::Legacy => 2,
+            EffectType::LowPass | EffectType::HighPass | EffectType::BandPass
+            | EffectType::Notch | EffectType::Distortion | EffectType::Bitcrusher
+            | EffectType::Compressor => 2,
+            EffectType::Reverb | EffectType::Echo | EffectType::Chorus | EffectType::Flanger => 3,
+        }
+    }
 }
 
-fn decode_parameters(bits: &[u8], wave_function: Option<WaveFunction>) -> DecodedParameters {
-    let start_time = bits_to_duration(&bits[0..8]);
-    let frequency = bits_to_frequency(&bits[8..16]);
-    let amplitude = bits_to_amplitude(&bits[16..24]);
-    let duration = bits_to_duration(&bits[24..32]);
-    let phase = bits_to_phase(&bits[32..40]);
+fn decode_parameters(
+    bits: BitSlice, wave_function: Option<WaveFunction>, note_decoder: &NoteDecoder
+) -> DecodedParameters {
+    let start_time = note_decoder.rhythm.start_time(bits_to_value(bits.slice(0..8)));
+    let frequency = note_decoder.pitch.frequency(bits_to_value(bits.slice(8..16)), 255);
+    let amplitude = bits_to_amplitude(bits.slice(16..24));
+    let duration = note_decoder.rhythm.duration(bits_to_value(bits.slice(24..32)));
+    let phase = bits_to_phase(bits.slice(32..40));
+    let envelope = if bits.len() >= 40 + ENVELOPE_BITS {
+        decode_envelope(bits.slice(40..40 + ENVELOPE_BITS))
+    } else {
+        Envelope::default()
+    };
+    let pan_start = 40 + ENVELOPE_BITS;
+    let pan = if bits.len() >= pan_start + PAN_BITS {
+        bits_to_value(bits.slice(pan_start..pan_start + PAN_BITS)) as f32 / 255.0 * 2.0 - 1.0
+    } else {
+        0.0
+    };
     DecodedParameters {
         start_time,
         frequency,
@@ -154,36 +558,187 @@ fn decode_parameters(bits: &[u8], wave_function: Option<WaveFunction>) -> Decode
         duration,
         phase,
         wave_function,
+        envelope,
+        pan,
     }
 }
 
-fn bits_to_frequency(bits: &[u8]) -> f32 {
-    let value = bits_to_value(bits);
-    value as f32 * 5.0 // Frequency range from 0 to 1275 Hz
+/// Reads the envelope genes that follow the parameters of an extended note. The times grow with
+/// the square of their gene so short, percussive settings get the finest steps, and the attack
+/// and release never quite reach zero so no envelope can click.
+fn decode_envelope(bits: BitSlice) -> Envelope {
+    let attack = bits_to_value(bits.slice(0..4)) as u64;
+    let decay = bits_to_value(bits.slice(4..8)) as u64;
+    let sustain = bits_to_value(bits.slice(8..11));
+    let release = bits_to_value(bits.slice(11..15)) as u64;
+    Envelope {
+        attack: Duration::from_millis(1 + attack * attack * 2), // Up to 451 ms
+        decay: Duration::from_millis(decay * decay * 4), // Up to 900 ms
+        sustain: sustain as f32 / 7.0,
+        release: Duration::from_millis(5 + release * release * 4), // Up to 905 ms
+        curve: if bits.get(15) == 1 { EnvelopeCurve::Exponential } else { EnvelopeCurve::Linear },
+    }
 }
 
-fn bits_to_amplitude(bits: &[u8]) -> f32 {
+fn bits_to_amplitude(bits: BitSlice) -> f32 {
     let value = bits_to_value(bits);
     value as f32 / 128.0 // Normalized amplitude between 0.0 and 1.0
 }
 
-fn bits_to_duration(bits: &[u8]) -> Duration {
+/// Maps the gene onto 0 to 7.65 seconds in 30 ms steps, as the legacy delays were read.
+fn bits_to_duration(bits: BitSlice) -> Duration {
+    Duration::from_millis(bits_to_value(bits) as u64 * 30)
+}
+
+/// Maps the gene onto 0.0 to 1.0.
+fn bits_to_fraction(bits: BitSlice) -> f32 {
+    bits_to_value(bits) as f32 / 255.0
+}
+
+/// Maps the gene onto 20 Hz to 20 kHz on a logarithmic scale, so each step is the same musical
+/// interval, about half a semitone, wherever it falls in the range.
+fn bits_to_cutoff(bits: BitSlice) -> f32 {
     let value = bits_to_value(bits);
-    // Duration range from 0 to 30 seconds
-    Duration::from_millis(value as u64 * 30)
+    20.0 * 1000f32.powf(value as f32 / 255.0)
 }
 
-fn bits_to_phase(bits: &[u8]) -> f32 {
+/// Maps the gene onto a Q of 0.5 to 10 on a logarithmic scale: from a gentle, wide slope to a
+/// sharp resonant peak.
+fn bits_to_q(bits: BitSlice) -> f32 {
+    let value = bits_to_value(bits);
+    0.5 * 20f32.powf(value as f32 / 255.0)
+}
+
+fn bits_to_phase(bits: BitSlice) -> f32 {
     let value = bits_to_value(bits);
     value as f32 * 2.0 * PI / 255.0 // Phase between 0 and 2π
 }
 
-fn bits_to_value(bits: &[u8]) -> u32 {
-    bits.iter().rev().enumerate().fold(0u32, |acc, (i, &bit)| {
-        acc + ((bit as u32) << i)
-    })
+fn bits_to_value(bits: BitSlice) -> u32 {
+    bits.value() as u32
 }
 
-fn matches_codon(segment: &[u8], codon: &[u8]) -> bool {
-    segment.len() == codon.len() && segment.iter().zip(codon.iter()).all(|(&a, &b)| a == b)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome::Chromosome;
+    use crate::rng::seeded_rng;
+    use crate::serialise_genome::FORMAT_VERSION;
+
+    /// A random genome relabelled as having been loaded from a file of format `version`.
+    fn genome_of_version(seed: u64, version: u16) -> Genome {
+        let genome = Genome::initialise_random_genome_with_rng(
+            2000, 3000, 4, 8, &mut seeded_rng(seed));
+        Genome::with_version(genome.chromosomes().to_vec(), version)
+    }
+
+    /// A genome of format `version` whose chromosomes are empty but for the named ones, given as
+    /// strings of 0s and 1s (spaces are ignored) carried by both strands.
+    fn genome_with(version: u16, genes: &[(&str, &str)]) -> Genome {
+        let chromosomes = SCHEMA
+            .chromosomes
+            .iter()
+            .map(|spec| {
+                let bits: BitVec = genes
+                    .iter()
+                    .filter(|(name, _)| *name == spec.name)
+                    .flat_map(|(_, bits)| bits.bytes().filter(|&bit| bit != b' '))
+                    .map(|bit| bit - b'0')
+                    .collect();
+                Chromosome::new(bits.clone(), bits)
+            })
+            .collect();
+        Genome::with_version(chromosomes, version)
+    }
+
+    fn decoded_effects(genome: &Genome) -> String {
+        let decoded = DecodedGenome::decode(genome);
+        let effects: Vec<&Effect> = decoded.effects.iter().map(|scoped| &scoped.effect).collect();
+        format!("{:?}", effects)
+    }
+
+    fn decode_with_layout(genome: &Genome, note_layout: NoteLayout) -> DecodedGenome {
+        let config = DecodeConfig { note_layout: Some(note_layout), ..DecodeConfig::default() };
+        DecodedGenome::decode_with(genome, &config)
+    }
+
+    #[test]
+    fn notes_follow_the_genome_version() {
+        let layouts = [
+            (1, NoteLayout::Legacy), (3, NoteLayout::Legacy), (4, NoteLayout::Extended),
+            (FORMAT_VERSION, NoteLayout::Extended),
+        ];
+        for (version, layout) in layouts {
+            let genome = genome_of_version(version as u64, version);
+            assert_eq!(
+                format!("{:?}", DecodedGenome::decode(&genome).notes),
+                format!("{:?}", decode_with_layout(&genome, layout).notes),
+                "version {}", version
+            );
+        }
+    }
+
+    #[test]
+    fn filters_follow_the_genome_version() {
+        let codons = [("low_pass_codon", "1010"), ("high_pass_codon", "1100")];
+        let legacy = genome_with(5, &[
+            codons[0], codons[1],
+            ("effects", "1010 01000000 1100 10000000"),
+        ]);
+        assert_eq!(decoded_effects(&legacy), "[LegacyLowPass(0.5), LegacyHighPass(1.0)]");
+
+        let biquads = genome_with(6, &[
+            codons[0], codons[1],
+            ("effects", "1010 00000000 11111111 1100 11111111 00000000"),
+        ]);
+        assert_eq!(decoded_effects(&biquads), "[LowPass(20.0, 10.0), HighPass(20000.0, 0.5)]");
+    }
+
+    #[test]
+    fn reverb_follows_the_genome_version() {
+        let comb = genome_with(5, &[
+            ("reverb_codon", "0110"),
+            ("effects", "0110 00000010 01000000"),
+        ]);
+        assert_eq!(decoded_effects(&comb), "[LegacyReverb(60ms, 0.5)]");
+
+        let freeverb = genome_with(6, &[
+            ("reverb_codon", "0110"),
+            ("effects", "0110 00000000 11111111 00000000"),
+        ]);
+        assert_eq!(decoded_effects(&freeverb), "[Reverb(0.0, 1.0, 0.0)]");
+    }
+
+    #[test]
+    fn echo_follows_the_genome_version() {
+        let single = genome_with(5, &[
+            ("echo_codon", "0011"),
+            ("effects", "0011 00000100 00100000"),
+        ]);
+        assert_eq!(decoded_effects(&single), "[LegacyEcho(120ms, 0.25)]");
+
+        let filtered = genome_with(6, &[
+            ("echo_codon", "0011"),
+            ("effects", "0011 00000000 11111111 01111111"),
+        ]);
+        assert_eq!(decoded_effects(&filtered), "[Echo(10ms, 0.9, 20000.0, 4)]");
+    }
+
+    #[test]
+    fn scopes_follow_the_genome_version() {
+        // Two distortions, the second scoped to the square voice when scopes are read.
+        let distortion = |scope: &str| format!("0101 00000000 11111111 {}", scope);
+        let unscoped = format!("{} {}", distortion(""), distortion(""));
+        let scoped = format!("{} {}", distortion("00000000"), distortion("10001000"));
+        let scopes = |version, genes: &str| {
+            let genome = genome_with(version, &[("distortion_codon", "0101"), ("effects", genes)]);
+            let decoded = DecodedGenome::decode(&genome);
+            decoded.effects.iter().map(|effect| effect.scope).collect::<Vec<_>>()
+        };
+        assert_eq!(scopes(7, &unscoped), [EffectScope::Global; 2]);
+        assert_eq!(
+            scopes(FORMAT_VERSION, &scoped),
+            [EffectScope::Global, EffectScope::Voice(WaveFunction::Square)]
+        );
+    }
 }
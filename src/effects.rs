@@ -0,0 +1,743 @@
+use std::f64::consts::{FRAC_1_SQRT_2, PI};
+use std::time::Duration;
+
+use crate::decode_genome::Effect;
+use crate::mastering::Biquad;
+
+/// Delays of the reverb's parallel combs and series all-passes at 44.1 kHz, from Freeverb. The
+/// lengths are mutually prime so that the echoes of the combs do not pile up on each other.
+const REVERB_COMBS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const REVERB_ALL_PASSES: [usize; 4] = [556, 441, 341, 225];
+/// Delay the chorus sweeps around, and the shortest delay of the flanger's sweep.
+const CHORUS_DELAY: f32 = 0.02;
+const FLANGER_DELAY: f32 = 0.0002;
+/// Attack and release times of the compressor's level detector, in seconds.
+const COMPRESSOR_ATTACK: f32 = 0.01;
+const COMPRESSOR_RELEASE: f32 = 0.1;
+
+/// How many samples longer the right side's reverb delays are than the left's in stereo, so that
+/// the two tails decorrelate.
+const REVERB_SPREAD: usize = 23;
+/// How much longer the right side's delay is than the left's in the legacy stereo reverb.
+const LEGACY_REVERB_SPREAD: f32 = 1.13;
+
+/// An effect that processes audio a block at a time, carrying its state (filter memories, delay
+/// lines) from one block to the next, so that a song processed in blocks sounds exactly as if it
+/// had been processed in one pass.
+pub trait EffectProcessor: Send {
+    /// Processes one block in place. `planes` holds one slice per channel, all the same length.
+    fn process(&mut self, planes: &mut [&mut [f32]]);
+}
+
+/// Builds the processor for one decoded effect. Stereo output (two channels) gets the stereo
+/// versions of the reverb and echo, and sweeps the two sides of the chorus and flanger out of
+/// step; any other channel count processes each channel alike.
+pub fn effect_processor(
+    effect: &Effect, sample_rate: u32, channels: usize, reverb_width: f32
+) -> Box<dyn EffectProcessor> {
+    let samples = |delay: &Duration| (delay.as_secs_f32() * sample_rate as f32) as usize;
+    // A line long enough for a swept delay of up to `longest` seconds, interpolation included.
+    let sweep_line = |longest: f32| DelayLine::new((longest * sample_rate as f32) as usize + 2);
+    match effect {
+        Effect::LowPass(cutoff, q) => {
+            Box::new(Filter::new(Response::LowPass, *cutoff, *q, sample_rate, channels))
+        }
+        Effect::HighPass(cutoff, q) => {
+            Box::new(Filter::new(Response::HighPass, *cutoff, *q, sample_rate, channels))
+        }
+        Effect::BandPass(centre, q) => {
+            Box::new(Filter::new(Response::BandPass, *centre, *q, sample_rate, channels))
+        }
+        Effect::Notch(centre, q) => {
+            Box::new(Filter::new(Response::Notch, *centre, *q, sample_rate, channels))
+        }
+        Effect::LegacyLowPass(coefficient) => Box::new(LegacyLowPass {
+            coefficient: *coefficient,
+            previous: vec![0.0; channels],
+        }),
+        Effect::LegacyHighPass(coefficient) => Box::new(LegacyHighPass {
+            coefficient: *coefficient,
+            previous: vec![0.0; channels],
+        }),
+        Effect::LegacyReverb(delay, feedback) if channels == 2 => Box::new(LegacyStereoReverb {
+            left: DelayLine::new(samples(delay)),
+            right: DelayLine::new(samples(&delay.mul_f32(LEGACY_REVERB_SPREAD))),
+            feedback: feedback.min(0.95),
+            width: reverb_width,
+        }),
+        Effect::LegacyReverb(delay, feedback) => Box::new(LegacyReverb {
+            lines: (0..channels).map(|_| DelayLine::new(samples(delay))).collect(),
+            feedback: feedback.min(0.95),
+        }),
+        Effect::LegacyEcho(delay, feedback) if channels == 2 => Box::new(LegacyPingPongEcho {
+            left: DelayLine::new(samples(delay)),
+            right: DelayLine::new(samples(delay)),
+            feedback: feedback.min(0.95),
+        }),
+        Effect::LegacyEcho(delay, feedback) => Box::new(LegacyEcho {
+            lines: (0..channels).map(|_| DelayLine::new(samples(delay))).collect(),
+            feedback: *feedback,
+        }),
+        Effect::Reverb(room_size, damping, mix) => {
+            let spreads = if channels == 2 { vec![0, REVERB_SPREAD] } else { vec![0; channels] };
+            Box::new(Reverb {
+                tanks: spreads
+                    .into_iter()
+                    .map(|spread| ReverbTank::new(sample_rate, spread, *room_size, *damping))
+                    .collect(),
+                mix: mix.clamp(0.0, 1.0),
+                width: if channels == 2 { reverb_width } else { 0.0 },
+                wet: Vec::new(),
+            })
+        }
+        Effect::Echo(delay, feedback, high_cut, taps) => {
+            let length = samples(delay);
+            let tap_count = (*taps).max(1);
+            let taps = (1..=tap_count).map(|tap| (tap * length / tap_count).max(1)).collect();
+            let high_cut =
+                || biquad(Response::LowPass, *high_cut, FRAC_1_SQRT_2, sample_rate);
+            let feedback = feedback.clamp(0.0, 0.95);
+            if channels == 2 {
+                Box::new(PingPongEcho {
+                    left: DelayLine::new(length),
+                    right: DelayLine::new(length),
+                    high_cuts: [high_cut(), high_cut()],
+                    feedback,
+                    taps,
+                })
+            } else {
+                Box::new(Echo {
+                    lines: (0..channels).map(|_| DelayLine::new(length)).collect(),
+                    high_cuts: (0..channels).map(|_| high_cut()).collect(),
+                    feedback,
+                    taps,
+                })
+            }
+        }
+        Effect::Distortion(drive, mix) => {
+            Box::new(Distortion { drive: drive.max(1.0), mix: mix.clamp(0.0, 1.0) })
+        }
+        Effect::Chorus(rate, depth, mix) => Box::new(Chorus {
+            sweep: Sweep::new(channels, *rate, sample_rate),
+            delay: CHORUS_DELAY * sample_rate as f32,
+            depth: depth.as_secs_f32() * sample_rate as f32,
+            lines: (0..channels).map(|_| sweep_line(CHORUS_DELAY + depth.as_secs_f32())).collect(),
+            mix: mix.clamp(0.0, 1.0),
+        }),
+        Effect::Flanger(rate, depth, feedback) => Box::new(Flanger {
+            sweep: Sweep::new(channels, *rate, sample_rate),
+            delay: FLANGER_DELAY * sample_rate as f32,
+            depth: depth.as_secs_f32() * sample_rate as f32,
+            lines: (0..channels).map(|_| sweep_line(FLANGER_DELAY + depth.as_secs_f32())).collect(),
+            feedback: feedback.clamp(0.0, 0.95),
+        }),
+        Effect::Bitcrusher(bits, hold) => Box::new(Bitcrusher {
+            levels: 2f32.powi((*bits).clamp(1, 24) as i32 - 1),
+            hold: (*hold).max(1),
+            held: vec![(0, 0.0); channels],
+        }),
+        Effect::Compressor(threshold, ratio) => {
+            let coefficient = |time: f32| (-1.0 / (time * sample_rate as f32)).exp();
+            Box::new(Compressor {
+                threshold: threshold.min(0.0),
+                ratio: ratio.max(1.0),
+                attack: coefficient(COMPRESSOR_ATTACK),
+                release: coefficient(COMPRESSOR_RELEASE),
+                level: 0.0,
+            })
+        }
+    }
+}
+
+/// A circular buffer delaying a signal by a whole number of samples. A zero-length line is a
+/// bypass: it reads back silence and stores nothing.
+struct DelayLine {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl DelayLine {
+    fn new(length: usize) -> Self {
+        DelayLine { buffer: vec![0.0; length], index: 0 }
+    }
+
+    fn is_bypass(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// The sample written `length` samples ago.
+    fn read(&self) -> f32 {
+        self.buffer.get(self.index).copied().unwrap_or(0.0)
+    }
+
+    /// The sample written `delay` samples ago, for a delay from 1 to the length of the line.
+    fn tap(&self, delay: usize) -> f32 {
+        if self.buffer.is_empty() {
+            return 0.0;
+        }
+        let length = self.buffer.len();
+        self.buffer[(self.index + length - delay.clamp(1, length)) % length]
+    }
+
+    /// Like `tap`, but for a delay between whole samples, interpolating linearly.
+    fn tap_fractional(&self, delay: f32) -> f32 {
+        let whole = delay.floor();
+        let fraction = delay - whole;
+        let whole = whole as usize;
+        self.tap(whole) * (1.0 - fraction) + self.tap(whole + 1) * fraction
+    }
+
+    /// Overwrites the sample just read and moves on to the next one.
+    fn write(&mut self, value: f32) {
+        if !self.buffer.is_empty() {
+            self.buffer[self.index] = value;
+            self.index = (self.index + 1) % self.buffer.len();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Response {
+    LowPass,
+    HighPass,
+    /// Unity gain at the centre frequency.
+    BandPass,
+    Notch,
+}
+
+/// A biquad filter on each channel, designed with the formulas of Robert Bristow-Johnson's Audio
+/// EQ Cookbook. The frequency is held below Nyquist so that every gene gives a stable filter.
+struct Filter {
+    sections: Vec<Biquad>,
+}
+
+impl Filter {
+    fn new(response: Response, frequency: f32, q: f32, sample_rate: u32, channels: usize) -> Self {
+        Filter { sections: vec![biquad(response, frequency, q as f64, sample_rate); channels] }
+    }
+}
+
+/// Designs one biquad section from the cookbook formulas.
+fn biquad(response: Response, frequency: f32, q: f64, sample_rate: u32) -> Biquad {
+    let sample_rate = sample_rate as f64;
+    let frequency = (frequency as f64).clamp(1.0, 0.45 * sample_rate);
+    let w0 = 2.0 * PI * frequency / sample_rate;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * q.max(0.1));
+
+    let b = match response {
+        Response::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+        Response::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+        Response::BandPass => [alpha, 0.0, -alpha],
+        Response::Notch => [1.0, -2.0 * cos, 1.0],
+    };
+    let a0 = 1.0 + alpha;
+    Biquad::new(
+        [b[0] / a0, b[1] / a0, b[2] / a0],
+        [-2.0 * cos / a0, (1.0 - alpha) / a0],
+    )
+}
+
+impl EffectProcessor for Filter {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, section) in planes.iter_mut().zip(self.sections.iter_mut()) {
+            for sample in plane.iter_mut() {
+                *sample = section.process(*sample as f64) as f32;
+            }
+        }
+    }
+}
+
+/// The one-pole low-pass that filtered genomes from before format version 6, kept so that they
+/// sound as they did.
+struct LegacyLowPass {
+    coefficient: f32,
+    previous: Vec<f32>,
+}
+
+impl EffectProcessor for LegacyLowPass {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, previous) in planes.iter_mut().zip(self.previous.iter_mut()) {
+            for sample in plane.iter_mut() {
+                *previous = *previous + self.coefficient * (*sample - *previous);
+                *sample = *previous;
+            }
+        }
+    }
+}
+
+/// The one-pole high-pass that filtered genomes from before format version 6.
+struct LegacyHighPass {
+    coefficient: f32,
+    previous: Vec<f32>,
+}
+
+impl EffectProcessor for LegacyHighPass {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, previous) in planes.iter_mut().zip(self.previous.iter_mut()) {
+            for sample in plane.iter_mut() {
+                let current = *sample;
+                *sample = current - *previous + self.coefficient * current;
+                *previous = current;
+            }
+        }
+    }
+}
+
+/// The feedback comb on each channel that was the reverb of genomes from before format version 6.
+/// Feedback is held below 1.0, where the original comb grew without bound.
+struct LegacyReverb {
+    lines: Vec<DelayLine>,
+    feedback: f32,
+}
+
+impl EffectProcessor for LegacyReverb {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, line) in planes.iter_mut().zip(self.lines.iter_mut()) {
+            if line.is_bypass() {
+                continue;
+            }
+            for sample in plane.iter_mut() {
+                *sample += line.read() * self.feedback;
+                line.write(*sample);
+            }
+        }
+    }
+}
+
+/// Runs the comb of `LegacyReverb` on each side with slightly different delays so that the two
+/// tails decorrelate. `width` scales the difference between the tails, from 0.0 for the same tail
+/// on both sides to 1.0 for fully independent ones.
+struct LegacyStereoReverb {
+    left: DelayLine,
+    right: DelayLine,
+    feedback: f32,
+    width: f32,
+}
+
+impl EffectProcessor for LegacyStereoReverb {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let [left, right] = planes else { return };
+        if self.left.is_bypass() {
+            return;
+        }
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let wet_left = self.left.read() * self.feedback;
+            let wet_right = self.right.read() * self.feedback;
+            self.left.write(*l + wet_left);
+            self.right.write(*r + wet_right);
+            let mid = (wet_left + wet_right) * 0.5;
+            let side = (wet_left - wet_right) * 0.5 * self.width;
+            *l += mid + side;
+            *r += mid - side;
+        }
+    }
+}
+
+/// A Freeverb-style reverb: each channel feeds a tank of eight damped combs in parallel followed
+/// by four all-passes in series. In stereo the right tank's delays are a little longer, and
+/// `width` mixes the two tails from the same tail on both sides (0.0) to fully independent ones
+/// (1.0).
+struct Reverb {
+    tanks: Vec<ReverbTank>,
+    mix: f32,
+    width: f32,
+    /// Scratch space for the tails of one block.
+    wet: Vec<Vec<f32>>,
+}
+
+impl EffectProcessor for Reverb {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        // Freeverb's gains: the input is scaled down before the combs, whose feedback sums
+        // to far more than unity, and the tail is scaled back up on the way out.
+        const INPUT_GAIN: f32 = 0.015;
+        const WET_GAIN: f32 = 3.0;
+
+        self.wet.resize_with(planes.len(), Vec::new);
+        let tanks = self.tanks.iter_mut().zip(self.wet.iter_mut());
+        for (plane, (tank, wet)) in planes.iter().zip(tanks) {
+            wet.clear();
+            wet.extend(plane.iter().map(|&sample| tank.process(sample * INPUT_GAIN)));
+        }
+
+        let wet_gain = self.mix * WET_GAIN;
+        let dry_gain = 1.0 - self.mix;
+        let same = wet_gain * (0.5 + self.width / 2.0);
+        let other = wet_gain * (0.5 - self.width / 2.0);
+        if let ([left, right], [wet_left, wet_right]) = (&mut *planes, self.wet.as_slice()) {
+            for (frame, (l, r)) in left.iter_mut().zip(right.iter_mut()).enumerate() {
+                *l = *l * dry_gain + wet_left[frame] * same + wet_right[frame] * other;
+                *r = *r * dry_gain + wet_right[frame] * same + wet_left[frame] * other;
+            }
+        } else {
+            for (plane, wet) in planes.iter_mut().zip(&self.wet) {
+                for (sample, wet) in plane.iter_mut().zip(wet) {
+                    *sample = *sample * dry_gain + wet * wet_gain;
+                }
+            }
+        }
+    }
+}
+
+/// The combs and all-passes of one channel of `Reverb`.
+struct ReverbTank {
+    combs: Vec<(DelayLine, f32)>,
+    all_passes: Vec<DelayLine>,
+    feedback: f32,
+    damping: f32,
+}
+
+impl ReverbTank {
+    /// The room size sets the combs' feedback, which is held below 1.0 so that the tail always
+    /// dies away, and the damping how quickly its high frequencies fade.
+    fn new(sample_rate: u32, spread: usize, room_size: f32, damping: f32) -> Self {
+        let scale = |length: usize| (length + spread) * sample_rate as usize / 44100;
+        ReverbTank {
+            combs: REVERB_COMBS
+                .iter()
+                .map(|&length| (DelayLine::new(scale(length)), 0.0))
+                .collect(),
+            all_passes: REVERB_ALL_PASSES
+                .iter()
+                .map(|&length| DelayLine::new(scale(length)))
+                .collect(),
+            feedback: 0.7 + 0.28 * room_size.clamp(0.0, 1.0),
+            damping: 0.4 * damping.clamp(0.0, 1.0),
+        }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let mut output = 0.0;
+        for (line, filter) in self.combs.iter_mut() {
+            let delayed = line.read();
+            *filter = delayed * (1.0 - self.damping) + *filter * self.damping;
+            line.write(input + *filter * self.feedback);
+            output += delayed;
+        }
+        for line in self.all_passes.iter_mut() {
+            let delayed = line.read();
+            line.write(output + delayed * 0.5);
+            output = delayed - output;
+        }
+        output
+    }
+}
+
+/// A feedback delay on each channel. The first repeat comes back at the input's level and every
+/// later one is quieter than the one before by `feedback`, each duller through the high-cut. With
+/// several taps the line is also read at even fractions of the delay time, filling in between
+/// the main repeats.
+struct Echo {
+    lines: Vec<DelayLine>,
+    high_cuts: Vec<Biquad>,
+    feedback: f32,
+    /// Delays of the taps in samples, the full length of the line last.
+    taps: Vec<usize>,
+}
+
+impl EffectProcessor for Echo {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let channels = self.lines.iter_mut().zip(self.high_cuts.iter_mut());
+        for (plane, (line, high_cut)) in planes.iter_mut().zip(channels) {
+            if line.is_bypass() {
+                continue;
+            }
+            for sample in plane.iter_mut() {
+                let repeats: f32 = self.taps.iter().map(|&delay| line.tap(delay)).sum();
+                let recirculated = *sample + self.feedback * line.read();
+                line.write(high_cut.process(recirculated as f64) as f32);
+                *sample += repeats;
+            }
+        }
+    }
+}
+
+/// An echo whose repeats bounce between the sides: both channels are delayed into the left, each
+/// left repeat is delayed again into the right, and each right repeat feeds back into the left.
+/// The first repeat keeps the level of the input; every bounce after it is scaled by `feedback`,
+/// and every repeat passes through the high-cut. Extra taps are heard on the side of the line
+/// they read.
+struct PingPongEcho {
+    left: DelayLine,
+    right: DelayLine,
+    high_cuts: [Biquad; 2],
+    feedback: f32,
+    taps: Vec<usize>,
+}
+
+impl EffectProcessor for PingPongEcho {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let [left, right] = planes else { return };
+        if self.left.is_bypass() {
+            return;
+        }
+        let [left_cut, right_cut] = &mut self.high_cuts;
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let left_repeats: f32 = self.taps.iter().map(|&delay| self.left.tap(delay)).sum();
+            let right_repeats: f32 = self.taps.iter().map(|&delay| self.right.tap(delay)).sum();
+            let from_left = self.left.read();
+            let from_right = self.right.read();
+            let into_left = (*l + *r) * 0.5 + self.feedback * from_right;
+            self.left.write(left_cut.process(into_left as f64) as f32);
+            self.right.write(right_cut.process((self.feedback * from_left) as f64) as f32);
+            *l += left_repeats;
+            *r += right_repeats;
+        }
+    }
+}
+
+/// The single repeat on each channel that was the echo of genomes from before format version 6.
+struct LegacyEcho {
+    lines: Vec<DelayLine>,
+    feedback: f32,
+}
+
+impl EffectProcessor for LegacyEcho {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, line) in planes.iter_mut().zip(self.lines.iter_mut()) {
+            if line.is_bypass() {
+                continue;
+            }
+            for sample in plane.iter_mut() {
+                let delayed = line.read();
+                line.write(*sample);
+                *sample += delayed * self.feedback;
+            }
+        }
+    }
+}
+
+/// The ping-pong echo of genomes from before format version 6, without taps or a high-cut, whose
+/// repeats lose `feedback` of their level at every bounce, the first included.
+struct LegacyPingPongEcho {
+    left: DelayLine,
+    right: DelayLine,
+    feedback: f32,
+}
+
+impl EffectProcessor for LegacyPingPongEcho {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let [left, right] = planes else { return };
+        if self.left.is_bypass() {
+            return;
+        }
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let from_left = self.left.read();
+            let from_right = self.right.read();
+            self.left.write(self.feedback * ((*l + *r) * 0.5 + from_right));
+            self.right.write(self.feedback * from_left);
+            *l += from_left;
+            *r += from_right;
+        }
+    }
+}
+
+/// A `tanh` waveshaper, scaled so that a full-scale input still peaks at full scale.
+struct Distortion {
+    drive: f32,
+    mix: f32,
+}
+
+impl EffectProcessor for Distortion {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let scale = 1.0 / self.drive.tanh();
+        for sample in planes.iter_mut().flat_map(|plane| plane.iter_mut()) {
+            let shaped = (*sample * self.drive).tanh() * scale;
+            *sample += (shaped - *sample) * self.mix;
+        }
+    }
+}
+
+/// A sine low-frequency oscillator for each channel. In stereo the right side runs a quarter of
+/// a cycle behind the left, which widens the sound.
+struct Sweep {
+    phases: Vec<f32>,
+    increment: f32,
+}
+
+impl Sweep {
+    fn new(channels: usize, rate: f32, sample_rate: u32) -> Self {
+        let offset = if channels == 2 { 0.25 } else { 0.0 };
+        Sweep {
+            phases: (0..channels).map(|channel| channel as f32 * offset).collect(),
+            increment: rate / sample_rate as f32,
+        }
+    }
+
+    /// Moves the channel's oscillator on a sample and returns its new value, from 0.0 to 1.0.
+    fn advance(&mut self, channel: usize) -> f32 {
+        let phase = &mut self.phases[channel];
+        *phase = (*phase + self.increment).fract();
+        0.5 + 0.5 * (std::f32::consts::TAU * *phase).sin()
+    }
+}
+
+/// Mixes in a copy of the signal whose delay sweeps slowly around `CHORUS_DELAY`, so it drifts
+/// in and out of tune with the original.
+struct Chorus {
+    sweep: Sweep,
+    /// Centre and depth of the sweep, in samples.
+    delay: f32,
+    depth: f32,
+    lines: Vec<DelayLine>,
+    mix: f32,
+}
+
+impl EffectProcessor for Chorus {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (channel, (plane, line)) in planes.iter_mut().zip(self.lines.iter_mut()).enumerate() {
+            for sample in plane.iter_mut() {
+                let delay = self.delay + self.depth * (self.sweep.advance(channel) - 0.5);
+                let wet = line.tap_fractional(delay);
+                line.write(*sample);
+                *sample += (wet - *sample) * self.mix;
+            }
+        }
+    }
+}
+
+/// Adds a copy of the signal delayed by a few milliseconds at most, with some of the copy fed
+/// back into the delay. As the delay sweeps, the notches of the resulting comb filter sweep
+/// through the spectrum. The copy is scaled down as the feedback rises so that the comb's peaks
+/// stay at unity gain.
+struct Flanger {
+    sweep: Sweep,
+    /// Shortest delay of the sweep and how far beyond it the sweep goes, in samples.
+    delay: f32,
+    depth: f32,
+    lines: Vec<DelayLine>,
+    feedback: f32,
+}
+
+impl EffectProcessor for Flanger {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (channel, (plane, line)) in planes.iter_mut().zip(self.lines.iter_mut()).enumerate() {
+            for sample in plane.iter_mut() {
+                let delay = self.delay + self.depth * self.sweep.advance(channel);
+                let wet = line.tap_fractional(delay);
+                line.write(*sample + wet * self.feedback);
+                *sample = (*sample + wet * (1.0 - self.feedback)) * 0.5;
+            }
+        }
+    }
+}
+
+/// Quantises the signal to fewer bits and holds each value for several samples, which lowers the
+/// effective sample rate and folds aliases back into the audible band.
+struct Bitcrusher {
+    /// Quantisation steps either side of zero.
+    levels: f32,
+    hold: usize,
+    /// For each channel, samples until the next value is taken and the value being held.
+    held: Vec<(usize, f32)>,
+}
+
+impl EffectProcessor for Bitcrusher {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        for (plane, (countdown, value)) in planes.iter_mut().zip(self.held.iter_mut()) {
+            for sample in plane.iter_mut() {
+                if *countdown == 0 {
+                    *value = (*sample * self.levels).round() / self.levels;
+                    *countdown = self.hold;
+                }
+                *countdown -= 1;
+                *sample = *value;
+            }
+        }
+    }
+}
+
+/// A feed-forward compressor. The level is detected from the loudest channel, so that a stereo
+/// image does not shift as the gain changes. There is no make-up gain; mastering brings the
+/// level back up.
+struct Compressor {
+    threshold: f32,
+    ratio: f32,
+    /// Smoothing coefficients of the level detector.
+    attack: f32,
+    release: f32,
+    /// Detected level, linear.
+    level: f32,
+}
+
+impl EffectProcessor for Compressor {
+    fn process(&mut self, planes: &mut [&mut [f32]]) {
+        let slope = 1.0 - 1.0 / self.ratio;
+        for frame in 0..planes.first().map_or(0, |plane| plane.len()) {
+            let peak = planes.iter().fold(0.0f32, |peak, plane| peak.max(plane[frame].abs()));
+            let coefficient = if peak > self.level { self.attack } else { self.release };
+            self.level = peak + (self.level - peak) * coefficient;
+
+            let level_db = 20.0 * self.level.max(1e-6).log10();
+            let reduction = (level_db - self.threshold).max(0.0) * slope;
+            let gain = 10f32.powf(-reduction / 20.0);
+            for plane in planes.iter_mut() {
+                plane[frame] *= gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    /// The effect's response to a unit impulse in every channel, one plane per channel.
+    fn impulse_response(effect: &Effect, channels: usize, frames: usize) -> Vec<Vec<f32>> {
+        let mut planes = vec![vec![0.0; frames]; channels];
+        for plane in &mut planes {
+            plane[0] = 1.0;
+        }
+        let mut processor = effect_processor(effect, RATE, channels, 1.0);
+        let mut slices: Vec<&mut [f32]> = planes.iter_mut().map(Vec::as_mut_slice).collect();
+        processor.process(&mut slices);
+        planes
+    }
+
+    /// The level of each repeat: the sum of the response over each delay period after the first.
+    fn repeat_levels(plane: &[f32], period: usize) -> Vec<f32> {
+        plane[period..].chunks(period).map(|chunk| chunk.iter().sum()).collect()
+    }
+
+    #[test]
+    fn echo_repeats_start_at_unity_and_fall_by_the_feedback() {
+        let echo = Effect::Echo(Duration::from_millis(10), 0.5, 20_000.0, 1);
+        let period = RATE as usize / 100;
+        let planes = impulse_response(&echo, 1, 5 * period);
+        let levels = repeat_levels(&planes[0], period);
+        for (level, expected) in levels.iter().zip([1.0, 0.5, 0.25, 0.125]) {
+            assert!((level - expected).abs() < 0.01, "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn ping_pong_repeats_start_at_unity_and_fall_by_the_feedback() {
+        let echo = Effect::Echo(Duration::from_millis(10), 0.5, 20_000.0, 1);
+        let period = RATE as usize / 100;
+        let planes = impulse_response(&echo, 2, 5 * period);
+        let (left, right) = (repeat_levels(&planes[0], period), repeat_levels(&planes[1], period));
+        // The first repeat lands on the left, then the repeats alternate sides.
+        let expected = [(1.0, 0.0), (0.0, 0.5), (0.25, 0.0), (0.0, 0.125)];
+        for (index, (l, r)) in expected.into_iter().enumerate() {
+            assert!((left[index] - l).abs() < 0.01, "left {:?}", left);
+            assert!((right[index] - r).abs() < 0.01, "right {:?}", right);
+        }
+    }
+
+    #[test]
+    fn legacy_reverb_decays_at_any_feedback() {
+        let reverb = Effect::LegacyReverb(Duration::from_millis(30), 1.99);
+        let period = RATE as usize * 3 / 100;
+        let energy = |chunk: &[f32]| chunk.iter().map(|sample| sample * sample).sum::<f32>();
+        for channels in [1, 2] {
+            for plane in impulse_response(&reverb, channels, 200 * period) {
+                assert!(plane.iter().all(|sample| sample.is_finite()));
+                let first = energy(&plane[..2 * period]);
+                let last = energy(&plane[plane.len() - 2 * period..]);
+                assert!(last < first * 1e-3, "{} channels: {} then {}", channels, first, last);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+/// An ADSR amplitude envelope. The note rises to full level over `attack`, falls to `sustain`
+/// over `decay` and holds there until the note's duration is over, then fades to silence over
+/// `release`. The release is added after the note's duration rather than taken out of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub attack: Duration,
+    pub decay: Duration,
+    /// Level held after the decay, from 0.0 to 1.0.
+    pub sustain: f32,
+    pub release: Duration,
+    pub curve: EnvelopeCurve,
+}
+
+/// Shape of each segment of an `Envelope`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvelopeCurve {
+    Linear,
+    /// Segments move quickly at first and settle gradually, like an analogue envelope.
+    Exponential,
+}
+
+impl Default for Envelope {
+    /// Just enough of a ramp at each end to keep notes from clicking, for genomes whose notes
+    /// carry no envelope genes.
+    fn default() -> Self {
+        Envelope {
+            attack: Duration::from_millis(5),
+            decay: Duration::ZERO,
+            sustain: 1.0,
+            release: Duration::from_millis(20),
+            curve: EnvelopeCurve::Linear,
+        }
+    }
+}
+
+impl Envelope {
+    /// Gain of the envelope `time` seconds after the note starts, for a note held for `held`
+    /// seconds. Zero once the release has finished.
+    pub fn gain(&self, time: f32, held: f32) -> f32 {
+        if time < held {
+            return self.held_gain(time);
+        }
+        let release = self.release.as_secs_f32();
+        let progress = if release > 0.0 { (time - held) / release } else { 1.0 };
+        if progress >= 1.0 {
+            return 0.0;
+        }
+        // The release starts from wherever the note had got to, which is below full level if it
+        // ended during its attack or decay.
+        self.fall(self.held_gain(held), 0.0, progress)
+    }
+
+    fn held_gain(&self, time: f32) -> f32 {
+        let attack = self.attack.as_secs_f32();
+        if time < attack {
+            return self.rise(time / attack);
+        }
+        let decay = self.decay.as_secs_f32();
+        if time < attack + decay {
+            return self.fall(1.0, self.sustain, (time - attack) / decay);
+        }
+        self.sustain
+    }
+
+    fn rise(&self, progress: f32) -> f32 {
+        match self.curve {
+            EnvelopeCurve::Linear => progress,
+            EnvelopeCurve::Exponential => 1.0 - (1.0 - progress).powi(3),
+        }
+    }
+
+    fn fall(&self, from: f32, to: f32, progress: f32) -> f32 {
+        let remaining = match self.curve {
+            EnvelopeCurve::Linear => 1.0 - progress,
+            EnvelopeCurve::Exponential => (1.0 - progress).powi(3),
+        };
+        to + (from - to) * remaining
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(curve: EnvelopeCurve) -> Envelope {
+        Envelope {
+            attack: Duration::from_millis(10),
+            decay: Duration::from_millis(20),
+            sustain: 0.5,
+            release: Duration::from_millis(40),
+            curve,
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn linear_segments_meet_at_their_boundaries() {
+        let envelope = envelope(EnvelopeCurve::Linear);
+        let gain = |ms: f32| envelope.gain(ms / 1000.0, 0.1);
+        let expected = [
+            (0.0, 0.0), (5.0, 0.5), (10.0, 1.0), (20.0, 0.75), (30.0, 0.5), (60.0, 0.5),
+            // The release runs from the end of the note's duration.
+            (100.0, 0.5), (120.0, 0.25), (140.0, 0.0), (500.0, 0.0),
+        ];
+        for (ms, level) in expected {
+            assert!(close(gain(ms), level), "{} ms: {} rather than {}", ms, gain(ms), level);
+        }
+    }
+
+    #[test]
+    fn exponential_segments_meet_at_their_boundaries() {
+        let envelope = envelope(EnvelopeCurve::Exponential);
+        let gain = |ms: f32| envelope.gain(ms / 1000.0, 0.1);
+        for (ms, level) in [(0.0, 0.0), (10.0, 1.0), (30.0, 0.5), (100.0, 0.5), (140.0, 0.0)] {
+            assert!(close(gain(ms), level), "{} ms: {} rather than {}", ms, gain(ms), level);
+        }
+        // Halfway through, each segment has already covered seven eighths of its way.
+        assert!(close(gain(5.0), 0.875));
+        assert!(close(gain(20.0), 0.5 + 0.5 / 8.0));
+        assert!(close(gain(120.0), 0.5 / 8.0));
+    }
+
+    #[test]
+    fn releasing_early_starts_from_the_level_reached() {
+        let envelope = envelope(EnvelopeCurve::Linear);
+        // A note let go halfway through its attack releases from half level.
+        assert!(close(envelope.gain(0.005, 0.005), 0.5));
+        assert!(close(envelope.gain(0.025, 0.005), 0.25));
+        assert!(close(envelope.gain(0.045, 0.005), 0.0));
+
+        let abrupt = Envelope { attack: Duration::ZERO, release: Duration::ZERO, ..envelope };
+        assert!(close(abrupt.gain(0.0, 0.1), 1.0));
+        assert_eq!(abrupt.gain(0.1, 0.1), 0.0);
+    }
+}
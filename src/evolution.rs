@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use crate::genome::Genome;
+use crate::graph::{Graph, Song};
+use crate::rng::{seeded_rng, SeededRng};
+
+/// A criterion for ending `Evolution::run`. The run stops as soon as any of its conditions holds.
+#[derive(Clone, Debug)]
+pub enum StopCondition {
+    /// Stop once the given generation has been evaluated.
+    Generations(usize),
+    /// Stop once any song scores at least this fitness.
+    FitnessThreshold(f32),
+    /// Stop once the best fitness seen so far has not improved by more than `min_improvement`
+    /// for `generations` consecutive generations.
+    Stagnation { generations: usize, min_improvement: f32 },
+}
+
+/// Summary of a single evaluated generation, handed to the per-generation callbacks.
+#[derive(Clone, Debug)]
+pub struct GenerationReport {
+    pub generation: usize,
+    pub population: usize,
+    pub best_fitness: f32,
+    pub mean_fitness: f32,
+    /// Id of the fittest song in the world, if the world is not empty.
+    pub best_song_id: Option<usize>,
+}
+
+type FitnessEvaluator = Box<dyn FnMut(&Song) -> f32>;
+type GenerationCallback = Box<dyn FnMut(&GenerationReport, &Graph)>;
+
+/// Drives the `Graph` world through successive generations: every song is scored, the fitness
+/// is written back through `Graph::calculate_fitness`, and the world then migrates and
+/// reproduces. All randomness comes from a single seeded generator, so a seed reproduces a run.
+pub struct Evolution {
+    pub graph: Graph,
+    rng: SeededRng,
+    fitness: FitnessEvaluator,
+    stop_conditions: Vec<StopCondition>,
+    callbacks: Vec<GenerationCallback>,
+    best_fitness: f32,
+    stagnant_generations: usize,
+}
+
+impl Evolution {
+    pub fn new(graph: Graph, seed: u64, fitness: impl FnMut(&Song) -> f32 + 'static) -> Self {
+        Evolution {
+            graph,
+            rng: seeded_rng(seed),
+            fitness: Box::new(fitness),
+            stop_conditions: Vec::new(),
+            callbacks: Vec::new(),
+            best_fitness: f32::NEG_INFINITY,
+            stagnant_generations: 0,
+        }
+    }
+
+    /// Fills every node up to its capacity with songs grown from random genomes. The arguments
+    /// are the chromosome length ranges passed to `Genome::initialise_random_genome`.
+    pub fn seed_nodes(
+        &mut self, large_chr_min: i32, large_chr_max: i32, small_chr_min: i32, small_chr_max: i32
+    ) {
+        let node_ids: Vec<usize> = self.graph.nodes.keys().copied().collect();
+        for node_id in node_ids {
+            let node = &self.graph.nodes[&node_id];
+            let free_slots = node.capacity.saturating_sub(node.songs.len());
+            for _ in 0..free_slots {
+                let genome = Genome::initialise_random_genome_with_rng(
+                    large_chr_min, large_chr_max, small_chr_min, small_chr_max, &mut self.rng
+                );
+                let song = self.graph.create_song(genome);
+                self.graph.add_song_to_node(node_id, song);
+            }
+        }
+    }
+
+    pub fn stop_when(&mut self, condition: StopCondition) {
+        self.stop_conditions.push(condition);
+    }
+
+    /// Registers a callback invoked after every generation has been evaluated, before it
+    /// reproduces.
+    pub fn on_generation(&mut self, callback: impl FnMut(&GenerationReport, &Graph) + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Evaluates the current generation, reports it, and then migrates and reproduces. Returns
+    /// the report of the generation that was evaluated.
+    pub fn step(&mut self) -> GenerationReport {
+        let report = self.evaluate();
+        self.advance();
+        report
+    }
+
+    /// Runs generations until one of the stop conditions holds and returns the report of the
+    /// final generation. The final generation is evaluated but does not reproduce, so the world
+    /// is left holding the songs the report describes.
+    pub fn run(&mut self) -> GenerationReport {
+        assert!(
+            !self.stop_conditions.is_empty(),
+            "Evolution::run needs at least one stop condition"
+        );
+        loop {
+            let report = self.evaluate();
+            if self.should_stop(&report) {
+                return report;
+            }
+            self.advance();
+        }
+    }
+
+    fn evaluate(&mut self) -> GenerationReport {
+        let mut ratings: HashMap<usize, Vec<f32>> = HashMap::new();
+        for (&node_id, node) in &self.graph.nodes {
+            let scores = node.songs.iter().map(|song| (self.fitness)(song)).collect();
+            ratings.insert(node_id, scores);
+        }
+        self.graph.calculate_fitness(&ratings);
+
+        let report = self.report();
+        if report.best_fitness > self.best_fitness + self.min_improvement() {
+            self.stagnant_generations = 0;
+        } else {
+            self.stagnant_generations += 1;
+        }
+        self.best_fitness = self.best_fitness.max(report.best_fitness);
+
+        for callback in &mut self.callbacks {
+            callback(&report, &self.graph);
+        }
+        report
+    }
+
+    fn advance(&mut self) {
+        let migrations = self.graph.calculate_migrations_with_rng(&mut self.rng);
+        self.graph.reproduce_songs_with_rng(&migrations, &mut self.rng);
+    }
+
+    fn report(&self) -> GenerationReport {
+        let songs = self.graph.nodes.values().flat_map(|node| node.songs.iter());
+        let mut population = 0;
+        let mut total_fitness = 0.0;
+        let mut best: Option<&Song> = None;
+        for song in songs {
+            population += 1;
+            total_fitness += song.fitness;
+            if best.is_none_or(|b| song.fitness > b.fitness) {
+                best = Some(song);
+            }
+        }
+
+        GenerationReport {
+            generation: self.graph.generation,
+            population,
+            best_fitness: best.map_or(0.0, |song| song.fitness),
+            mean_fitness: if population > 0 { total_fitness / population as f32 } else { 0.0 },
+            best_song_id: best.map(|song| song.id),
+        }
+    }
+
+    fn should_stop(&self, report: &GenerationReport) -> bool {
+        self.stop_conditions.iter().any(|condition| match *condition {
+            StopCondition::Generations(generations) => report.generation >= generations,
+            StopCondition::FitnessThreshold(threshold) => report.best_fitness >= threshold,
+            StopCondition::Stagnation { generations, .. } => {
+                self.stagnant_generations >= generations
+            }
+        })
+    }
+
+    fn min_improvement(&self) -> f32 {
+        self.stop_conditions
+            .iter()
+            .find_map(|condition| match *condition {
+                StopCondition::Stagnation { min_improvement, .. } => Some(min_improvement),
+                _ => None,
+            })
+            .unwrap_or(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialise_genome::genome_to_bytes;
+
+    /// Runs a small two-node world and returns every surviving song's id and saved genome.
+    fn run(seed: u64) -> Vec<(usize, Vec<u8>)> {
+        let mut world = Graph::new();
+        world.add_node(0, 6);
+        world.add_node(1, 6);
+        world.add_edge(0, 1, 0.3);
+        world.add_edge(1, 0, 0.3);
+        let mut evolution = Evolution::new(world, seed, |song| song.decoded.notes.len() as f32);
+        evolution.seed_nodes(128, 256, 6, 8);
+        evolution.stop_when(StopCondition::Generations(5));
+        evolution.run();
+        evolution
+            .graph
+            .nodes
+            .values()
+            .flat_map(|node| node.songs.iter())
+            .map(|song| (song.id, genome_to_bytes(&song.genome)))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_reproduces_every_genome() {
+        assert_eq!(run(42), run(42));
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        assert_ne!(run(42), run(43));
+    }
+
+    #[test]
+    fn scores_that_are_not_numbers_do_not_stop_the_run() {
+        let mut world = Graph::new();
+        world.add_node(0, 6);
+        let mut evolution = Evolution::new(world, 7, |song| match song.id % 3 {
+            0 => f32::NAN,
+            1 => f32::INFINITY,
+            _ => 1.0,
+        });
+        evolution.seed_nodes(128, 256, 6, 8);
+        evolution.stop_when(StopCondition::Generations(3));
+        evolution.run();
+        assert_eq!(evolution.graph.generation, 3);
+    }
+}
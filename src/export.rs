@@ -0,0 +1,172 @@
+use std::error::Error;
+use std::path::Path;
+
+use crate::decode_genome::DecodedGenome;
+use crate::flac::{encode_flac, encode_ogg_flac};
+use crate::graph::Graph;
+use crate::mastering::quantise_i16;
+use crate::render::{render, AudioBuffer, RenderConfig};
+use crate::vorbis::encode_ogg_vorbis;
+
+/// Container of an exported file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Wav(SampleFormat),
+    /// Lossless FLAC. Only the integer sample formats can be stored.
+    Flac(SampleFormat),
+    /// Lossless FLAC in an Ogg container, for players that want Ogg but not lossy audio. Only the
+    /// integer sample formats can be stored.
+    OggFlac(SampleFormat),
+    /// Lossy Ogg Vorbis at a quality from 0, the smallest files, to 10. Samples are encoded as
+    /// rendered, so the render's dither setting has no effect.
+    OggVorbis { quality: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    /// 16-bit integers, dithered if the render's mastering asks for it.
+    Int16,
+    Int24,
+    Float32,
+}
+
+impl ExportFormat {
+    /// The usual file extension for the format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Wav(_) => "wav",
+            ExportFormat::Flac(_) => "flac",
+            ExportFormat::OggFlac(_) => "oga",
+            ExportFormat::OggVorbis { .. } => "ogg",
+        }
+    }
+}
+
+/// What to export: how the song is rendered, which sets the sample rate and channel count, and
+/// the format it is encoded in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExportConfig {
+    pub render: RenderConfig,
+    pub format: ExportFormat,
+}
+
+impl Default for ExportConfig {
+    /// 16-bit WAV of the default render.
+    fn default() -> Self {
+        ExportConfig {
+            render: RenderConfig::default(),
+            format: ExportFormat::Wav(SampleFormat::Int16),
+        }
+    }
+}
+
+/// Renders the decoded genome and encodes it as a complete file in memory.
+pub fn export(decoded: &DecodedGenome, config: &ExportConfig) -> Result<Vec<u8>, Box<dyn Error>> {
+    let buffer = render(decoded, &config.render);
+    let dither = config.render.mastering.is_some_and(|mastering| mastering.dither);
+    encode(&buffer, config.format, dither)
+}
+
+/// Renders the decoded genome and writes it to `filename`.
+pub fn export_file(
+    decoded: &DecodedGenome, config: &ExportConfig, filename: &str
+) -> Result<(), Box<dyn Error>> {
+    std::fs::write(filename, export(decoded, config)?)?;
+    Ok(())
+}
+
+/// Exports every song in the graph into `directory`, naming each file after the song's id, and
+/// returns the paths written. The directory is created if it does not exist.
+pub fn export_population(
+    graph: &Graph, directory: &str, config: &ExportConfig
+) -> Result<Vec<String>, Box<dyn Error>> {
+    std::fs::create_dir_all(directory)?;
+    let mut paths = Vec::new();
+    for song in graph.nodes.values().flat_map(|node| node.songs.iter()) {
+        let path = Path::new(directory)
+            .join(format!("song_{}.{}", song.id, config.format.extension()))
+            .to_string_lossy()
+            .into_owned();
+        export_file(&song.decoded, config, &path)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Encodes an already rendered buffer.
+pub fn encode(
+    buffer: &AudioBuffer, format: ExportFormat, dither: bool
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    match format {
+        ExportFormat::Wav(sample_format) => encode_wav(buffer, sample_format, dither),
+        ExportFormat::OggVorbis { quality } => {
+            encode_ogg_vorbis(&buffer.samples, buffer.channels, buffer.sample_rate, quality)
+        }
+        ExportFormat::Flac(sample_format) | ExportFormat::OggFlac(sample_format) => {
+            let (samples, bits) = match sample_format {
+                SampleFormat::Int16 => (
+                    quantise_i16(&buffer.samples, dither).into_iter().map(i32::from).collect(),
+                    16,
+                ),
+                SampleFormat::Int24 => (quantise_i24(&buffer.samples), 24),
+                SampleFormat::Float32 => {
+                    return Err("FLAC cannot store floating-point samples".into());
+                }
+            };
+            if let ExportFormat::Flac(_) = format {
+                encode_flac(&samples, buffer.channels, buffer.sample_rate, bits)
+            } else {
+                encode_ogg_flac(&samples, buffer.channels, buffer.sample_rate, bits)
+            }
+        }
+    }
+}
+
+fn encode_wav(
+    buffer: &AudioBuffer, sample_format: SampleFormat, dither: bool
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (bits_per_sample, format) = match sample_format {
+        SampleFormat::Int16 => (16, hound::SampleFormat::Int),
+        SampleFormat::Int24 => (24, hound::SampleFormat::Int),
+        SampleFormat::Float32 => (32, hound::SampleFormat::Float),
+    };
+    let spec = hound::WavSpec {
+        channels: buffer.channels,
+        sample_rate: buffer.sample_rate,
+        bits_per_sample,
+        sample_format: format,
+    };
+
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    match sample_format {
+        SampleFormat::Int16 => {
+            for sample in quantise_i16(&buffer.samples, dither) {
+                writer.write_sample(sample)?;
+            }
+        }
+        SampleFormat::Int24 => {
+            for sample in quantise_i24(&buffer.samples) {
+                writer.write_sample(sample)?;
+            }
+        }
+        SampleFormat::Float32 => {
+            for &sample in &buffer.samples {
+                writer.write_sample(sample)?;
+            }
+        }
+    }
+    writer.finalize()?;
+
+    Ok(cursor.into_inner())
+}
+
+/// Quantises samples in [-1.0, 1.0] to 24 bits. Rounding error at this depth is far below
+/// audibility, so no dither is added.
+fn quantise_i24(samples: &[f32]) -> Vec<i32> {
+    const MAX: f32 = 8_388_607.0;
+    samples
+        .iter()
+        .map(|&sample| (sample * MAX).round().clamp(-MAX - 1.0, MAX) as i32)
+        .collect()
+}
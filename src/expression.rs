@@ -0,0 +1,201 @@
+use crate::bits::{BitSlice, BitVec};
+use crate::genome::{Chromosome, Genome};
+use crate::genome_schema::DOMINANCE;
+
+/// How the two strands of each chromosome combine into the bits that are decoded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ExpressionModel {
+    /// Only the left strand is read; the right strand is carried but never heard.
+    #[default]
+    LeftOnly,
+    /// Each locus of each chromosome expresses whichever strand is dominant there. Which strand
+    /// that is is fixed by `seed`, so it is the same for every genome decoded with this model.
+    RandomDominance { seed: u64 },
+    /// Co-dominance: both strands of the note chromosome are expressed as separate voices, the
+    /// left strand's notes read with the left strands of the codons and the right strand's with
+    /// the right. Every other chromosome is read from its left strand.
+    Layered,
+    /// The genome's own dominance chromosome decides: a 1 at a locus on either of its strands
+    /// makes the right strand dominant there. The mask repeats to cover chromosomes longer than
+    /// itself.
+    DominanceMask,
+}
+
+/// Applies an `ExpressionModel` to the chromosomes of one genome.
+pub struct Expression<'a> {
+    model: &'a ExpressionModel,
+    mask: BitVec,
+}
+
+impl<'a> Expression<'a> {
+    pub fn new(model: &'a ExpressionModel, genome: &Genome) -> Self {
+        let mask = match model {
+            ExpressionModel::DominanceMask => {
+                let left = genome[DOMINANCE].get_left_chromosome();
+                let right = genome[DOMINANCE].get_right_chromosome();
+                let common = left.len().min(right.len());
+                let mut mask = BitVec::with_capacity(left.len().max(right.len()));
+                let pairs = left.slice(..common).chunks(64).zip(right.slice(..common).chunks(64));
+                for (l, r) in pairs {
+                    mask.push_word(l.value() | r.value(), l.len());
+                }
+                let longer = if left.len() > common { left } else { right };
+                mask.extend_from_bits(longer.slice(common..));
+                mask
+            }
+            _ => BitVec::new(),
+        };
+        Expression { model, mask }
+    }
+
+    /// How many voices the note chromosome is expressed as.
+    pub fn voices(&self) -> usize {
+        match self.model {
+            ExpressionModel::Layered => 2,
+            _ => 1,
+        }
+    }
+
+    /// The expressed bits of `chromosome` for `voice`, which must be below `voices()`. `name`
+    /// identifies the chromosome so random dominance differs from one chromosome to the next.
+    ///
+    /// Where one strand is longer than the other, its extra loci are expressed unopposed.
+    pub fn express(&self, chromosome: &Chromosome, name: &str, voice: usize) -> BitVec {
+        let left = chromosome.get_left_chromosome();
+        let right = chromosome.get_right_chromosome();
+        let common = left.len().min(right.len());
+        match self.model {
+            ExpressionModel::LeftOnly => left.to_bit_vec(),
+            ExpressionModel::Layered => {
+                if voice == 0 { left.to_bit_vec() } else { right.to_bit_vec() }
+            }
+            ExpressionModel::RandomDominance { seed } => {
+                let salt = seed ^ name_hash(name);
+                let dominance = (0..common).map(|locus| (mix(salt ^ locus as u64) & 1) as u8);
+                combine(left, right, &dominance.collect())
+            }
+            ExpressionModel::DominanceMask => {
+                let mut dominance = BitVec::with_capacity(common);
+                while !self.mask.is_empty() && dominance.len() < common {
+                    let repeat = self.mask.len().min(common - dominance.len());
+                    dominance.extend_from_bits(self.mask.slice(..repeat));
+                }
+                combine(left, right, &dominance)
+            }
+        }
+    }
+}
+
+/// Builds the expressed strand a word at a time, taking the right strand wherever
+/// `right_dominant` has a 1. Loci where only one strand exists, or beyond the end of
+/// `right_dominant`, come from whichever strand has them, the left if both do.
+fn combine(left: BitSlice, right: BitSlice, right_dominant: &BitVec) -> BitVec {
+    let common = left.len().min(right.len());
+    let dominant = right_dominant.len().min(common);
+    let mut expressed = BitVec::with_capacity(left.len().max(right.len()));
+    for ((l, r), mask) in left
+        .slice(..dominant)
+        .chunks(64)
+        .zip(right.slice(..dominant).chunks(64))
+        .zip(right_dominant.slice(..dominant).chunks(64))
+    {
+        let mask = mask.value();
+        expressed.push_word((l.value() & !mask) | (r.value() & mask), l.len());
+    }
+    expressed.extend_from_bits(left.slice(dominant..));
+    expressed.extend_from_bits(right.slice(left.len().min(right.len())..));
+    expressed
+}
+
+/// FNV-1a hash of a chromosome name.
+fn name_hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// SplitMix64 finaliser, turning neighbouring loci into unrelated coin flips.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome_schema::SCHEMA;
+
+    fn bits(text: &str) -> BitVec {
+        text.bytes().map(|bit| bit - b'0').collect()
+    }
+
+    fn chromosome(left: &str, right: &str) -> Chromosome {
+        Chromosome::new(bits(left), bits(right))
+    }
+
+    /// A genome with empty chromosomes apart from its dominance chromosome.
+    fn genome_with_dominance(left: &str, right: &str) -> Genome {
+        let mut chromosomes = vec![chromosome("", ""); SCHEMA.chromosomes.len()];
+        chromosomes[DOMINANCE] = chromosome(left, right);
+        Genome::new(chromosomes)
+    }
+
+    fn express(
+        model: &ExpressionModel, genome: &Genome, strands: &Chromosome, voice: usize
+    ) -> String {
+        Expression::new(model, genome).express(strands, "notes", voice).to_string()
+    }
+
+    #[test]
+    fn left_only_and_layered_read_whole_strands() {
+        let genome = genome_with_dominance("", "");
+        let strands = chromosome("0011", "0101");
+        let left_only = ExpressionModel::LeftOnly;
+        assert_eq!(Expression::new(&left_only, &genome).voices(), 1);
+        assert_eq!(express(&left_only, &genome, &strands, 0), "0011");
+
+        let layered = ExpressionModel::Layered;
+        assert_eq!(Expression::new(&layered, &genome).voices(), 2);
+        assert_eq!(express(&layered, &genome, &strands, 0), "0011");
+        assert_eq!(express(&layered, &genome, &strands, 1), "0101");
+    }
+
+    #[test]
+    fn dominance_mask_combines_both_strands_and_repeats() {
+        // Either strand's 1 makes the right strand dominant; the longer strand's tail is kept.
+        let genome = genome_with_dominance("01010", "0011");
+        let model = ExpressionModel::DominanceMask;
+        assert_eq!(Expression::new(&model, &genome).mask.to_string(), "01110");
+
+        // The mask repeats over the eight loci both strands share, and the left strand's extra
+        // locus is expressed unopposed.
+        let strands = chromosome("000000000", "11111111");
+        assert_eq!(express(&model, &genome, &strands, 0), "011100110");
+        let strands = chromosome("0000000", "111111111");
+        assert_eq!(express(&model, &genome, &strands, 0), "011100111");
+
+        // With no dominance genes every locus falls to the left strand.
+        let genome = genome_with_dominance("", "");
+        assert_eq!(express(&model, &genome, &chromosome("0000", "1111"), 0), "0000");
+    }
+
+    #[test]
+    fn random_dominance_depends_on_seed_and_chromosome() {
+        let genome = genome_with_dominance("", "");
+        let strands = chromosome(&"0".repeat(64), &"1".repeat(70));
+        let pattern = |seed, name| {
+            let model = ExpressionModel::RandomDominance { seed };
+            Expression::new(&model, &genome).express(&strands, name, 0)
+        };
+        let notes = pattern(1, "notes");
+        assert_eq!(notes, pattern(1, "notes"));
+        assert_ne!(notes, pattern(2, "notes"));
+        assert_ne!(notes, pattern(1, "effects"));
+        // Loci come from both strands, and the right strand's extra loci are unopposed.
+        let shared = notes.slice(..64).count_ones();
+        assert!(shared > 16 && shared < 48, "{}", shared);
+        assert_eq!(notes.slice(64..).to_string(), "111111");
+    }
+}
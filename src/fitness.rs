@@ -0,0 +1,281 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use crate::decode_genome::DecodedGenome;
+use crate::render::{render, RenderConfig};
+
+/// An automatic judge of how good a song is. Scores are expected to lie between 0.0 (worst) and
+/// 1.0 (best) so that functions can be combined with `WeightedFitness`.
+pub trait FitnessFunction {
+    /// Scores a song from its decoded phenotype and its rendered mono samples. `samples` is empty
+    /// when `needs_samples` returns false.
+    fn evaluate(&self, decoded: &DecodedGenome, samples: &[f32], sample_rate: u32) -> f32;
+
+    /// Whether `evaluate` looks at the rendered samples. Rendering dominates the cost of
+    /// evaluation, so functions that only inspect the note list should return false.
+    fn needs_samples(&self) -> bool {
+        true
+    }
+}
+
+/// Scores a decoded song, rendering it first only if the fitness function needs the audio. The
+/// samples are the raw mix, before mastering, so that penalties such as `ClippingPenalty` see the
+/// levels the genome actually produces.
+pub fn score(function: &dyn FitnessFunction, decoded: &DecodedGenome) -> f32 {
+    let config = RenderConfig { channels: 1, mastering: None, ..RenderConfig::default() };
+    if function.needs_samples() {
+        let buffer = render(decoded, &config);
+        function.evaluate(decoded, &buffer.samples, buffer.sample_rate)
+    } else {
+        function.evaluate(decoded, &[], config.sample_rate)
+    }
+}
+
+/// A weighted average of several fitness functions.
+#[derive(Default)]
+pub struct WeightedFitness {
+    components: Vec<(f32, Box<dyn FitnessFunction>)>,
+}
+
+impl WeightedFitness {
+    pub fn new() -> Self {
+        WeightedFitness { components: Vec::new() }
+    }
+
+    pub fn add(&mut self, weight: f32, function: impl FitnessFunction + 'static) {
+        self.components.push((weight, Box::new(function)));
+    }
+}
+
+impl FitnessFunction for WeightedFitness {
+    fn evaluate(&self, decoded: &DecodedGenome, samples: &[f32], sample_rate: u32) -> f32 {
+        let total_weight: f32 = self.components.iter().map(|(weight, _)| weight).sum();
+        if total_weight <= 0.0 {
+            return 0.0;
+        }
+        let weighted_sum: f32 = self.components
+            .iter()
+            .map(|(weight, function)| weight * function.evaluate(decoded, samples, sample_rate))
+            .sum();
+        weighted_sum / total_weight
+    }
+
+    fn needs_samples(&self) -> bool {
+        self.components.iter().any(|(_, function)| function.needs_samples())
+    }
+}
+
+/// Rewards notes that sound at the same time as each other in consonant intervals. Each pair of
+/// overlapping notes is scored by how close its frequency ratio, folded into one octave, lies to
+/// a just-intonation consonance.
+pub struct Consonance;
+
+const CONSONANT_RATIOS: [f32; 8] = [
+    1.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0, 3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 2.0,
+];
+
+impl FitnessFunction for Consonance {
+    fn evaluate(&self, decoded: &DecodedGenome, _samples: &[f32], _sample_rate: u32) -> f32 {
+        let notes: Vec<_> = decoded.notes.iter().filter(|n| n.frequency > 0.0).collect();
+        let mut total = 0.0;
+        let mut pairs = 0;
+        for (i, a) in notes.iter().enumerate() {
+            for b in &notes[i + 1..] {
+                let overlaps = a.start_time < b.start_time + b.duration
+                    && b.start_time < a.start_time + a.duration;
+                if !overlaps {
+                    continue;
+                }
+                let mut ratio = a.frequency.max(b.frequency) / a.frequency.min(b.frequency);
+                while ratio > 2.0 {
+                    ratio /= 2.0;
+                }
+                // Distance in cents to the nearest consonance; a quarter tone away scores zero.
+                let cents = CONSONANT_RATIOS
+                    .iter()
+                    .map(|target| (1200.0 * (ratio / target).log2()).abs())
+                    .fold(f32::INFINITY, f32::min);
+                total += (1.0 - cents / 50.0).max(0.0);
+                pairs += 1;
+            }
+        }
+        if pairs == 0 { 1.0 } else { total / pairs as f32 }
+    }
+
+    fn needs_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Rewards note onsets that fall on a regular pulse. The median gap between successive start
+/// times is taken as the beat, and each gap is scored by how close it is to a whole number of
+/// beats.
+pub struct RhythmicRegularity;
+
+impl FitnessFunction for RhythmicRegularity {
+    fn evaluate(&self, decoded: &DecodedGenome, _samples: &[f32], _sample_rate: u32) -> f32 {
+        let mut starts: Vec<f32> = decoded.notes
+            .iter()
+            .map(|n| n.start_time.as_secs_f32())
+            .collect();
+        starts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        starts.dedup();
+
+        let mut gaps: Vec<f32> = starts.windows(2).map(|w| w[1] - w[0]).collect();
+        if gaps.len() < 2 {
+            return 0.0;
+        }
+        gaps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let beat = gaps[gaps.len() / 2];
+
+        let total: f32 = gaps
+            .iter()
+            .map(|gap| {
+                let beats = gap / beat;
+                1.0 - 2.0 * (beats - beats.round()).abs()
+            })
+            .sum();
+        total / gaps.len() as f32
+    }
+
+    fn needs_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Scores how close the average spectral flatness of the audio is to `target`. Flatness is 0.0
+/// for a pure tone and approaches 1.0 for white noise.
+pub struct SpectralFlatness {
+    pub target: f32,
+}
+
+const SPECTRUM_FRAME: usize = 1024;
+
+impl FitnessFunction for SpectralFlatness {
+    fn evaluate(&self, _decoded: &DecodedGenome, samples: &[f32], _sample_rate: u32) -> f32 {
+        let mut total = 0.0;
+        let mut frames = 0;
+        for frame in samples.chunks_exact(SPECTRUM_FRAME).step_by(4) {
+            let spectrum = magnitude_spectrum(frame);
+            let power: Vec<f32> = spectrum.iter().map(|m| m * m + 1e-12).collect();
+            let arithmetic_mean = power.iter().sum::<f32>() / power.len() as f32;
+            // Frames of silence say nothing about the timbre.
+            if arithmetic_mean < 1e-8 {
+                continue;
+            }
+            let geometric_mean = (power.iter().map(|p| p.ln()).sum::<f32>()
+                / power.len() as f32).exp();
+            total += geometric_mean / arithmetic_mean;
+            frames += 1;
+        }
+        if frames == 0 {
+            return 0.0;
+        }
+        1.0 - (total / frames as f32 - self.target).abs().min(1.0)
+    }
+}
+
+/// Rewards songs whose length is close to `target`, falling to zero once they are `tolerance`
+/// too long or too short.
+pub struct TargetDuration {
+    pub target: Duration,
+    pub tolerance: Duration,
+}
+
+impl FitnessFunction for TargetDuration {
+    fn evaluate(&self, decoded: &DecodedGenome, _samples: &[f32], _sample_rate: u32) -> f32 {
+        let length = decoded.notes
+            .iter()
+            .map(|n| n.end_time())
+            .max()
+            .unwrap_or_default();
+        let difference = (length.as_secs_f32() - self.target.as_secs_f32()).abs();
+        1.0 - (difference / self.tolerance.as_secs_f32().max(1e-3)).min(1.0)
+    }
+
+    fn needs_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Penalises long stretches of silence: the score is the fraction of 10 ms windows whose RMS
+/// level is at least `threshold`.
+pub struct SilencePenalty {
+    pub threshold: f32,
+}
+
+impl FitnessFunction for SilencePenalty {
+    fn evaluate(&self, _decoded: &DecodedGenome, samples: &[f32], sample_rate: u32) -> f32 {
+        let window = (sample_rate as usize / 100).max(1);
+        let mut windows = 0;
+        let mut audible = 0;
+        for chunk in samples.chunks(window) {
+            let rms = (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt();
+            windows += 1;
+            if rms >= self.threshold {
+                audible += 1;
+            }
+        }
+        if windows == 0 { 0.0 } else { audible as f32 / windows as f32 }
+    }
+}
+
+/// Penalises clipping: the score is the fraction of samples that stay within [-1.0, 1.0].
+pub struct ClippingPenalty;
+
+impl FitnessFunction for ClippingPenalty {
+    fn evaluate(&self, _decoded: &DecodedGenome, samples: &[f32], _sample_rate: u32) -> f32 {
+        if samples.is_empty() {
+            return 0.0;
+        }
+        let clipped = samples.iter().filter(|s| s.abs() > 1.0).count();
+        1.0 - clipped as f32 / samples.len() as f32
+    }
+}
+
+/// Magnitudes of the positive-frequency bins of a Hann-windowed frame, whose length must be a
+/// power of two.
+fn magnitude_spectrum(frame: &[f32]) -> Vec<f32> {
+    let n = frame.len();
+    let mut re: Vec<f32> = frame
+        .iter()
+        .enumerate()
+        .map(|(i, s)| s * (0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos()))
+        .collect();
+    let mut im = vec![0.0; n];
+
+    // Iterative radix-2 Cooley-Tukey FFT.
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_im, w_re) = (angle * k as f32).sin_cos();
+                let a = start + k;
+                let b = a + len / 2;
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+
+    (1..n / 2).map(|k| (re[k] * re[k] + im[k] * im[k]).sqrt()).collect()
+}
@@ -0,0 +1,397 @@
+use std::error::Error;
+
+use crate::ogg::OggWriter;
+
+/// Samples per channel in every frame but the last.
+const BLOCK_SIZE: usize = 4096;
+
+/// Highest fixed-predictor order tried for each subframe.
+const MAX_ORDER: usize = 4;
+
+/// Largest Rice parameter the 4-bit residual coding method can express.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+const VENDOR: &str = "music_evo";
+
+/// Encodes interleaved integer samples as a native FLAC stream.
+///
+/// Each channel is coded independently with the best of the fixed polynomial predictors and a
+/// single Rice partition per subframe, which falls well short of the reference encoder's ratio
+/// but is lossless and quick. The stream carries no MD5 signature.
+pub fn encode_flac(
+    samples: &[i32], channels: u16, sample_rate: u32, bits_per_sample: u32
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_format(channels, sample_rate, bits_per_sample)?;
+    let frames = encode_frames(samples, channels, bits_per_sample);
+
+    let mut bytes = b"fLaC".to_vec();
+    bytes.extend(metadata_block_header(0, true, 34));
+    bytes.extend(stream_info(samples, channels, sample_rate, bits_per_sample, &frames));
+    for frame in frames {
+        bytes.extend(frame);
+    }
+    Ok(bytes)
+}
+
+/// Encodes interleaved integer samples as FLAC in an Ogg container, following the Ogg FLAC
+/// mapping: a first page identifying the stream and holding STREAMINFO, a page with a Vorbis
+/// comment block, and then one FLAC frame per packet.
+pub fn encode_ogg_flac(
+    samples: &[i32], channels: u16, sample_rate: u32, bits_per_sample: u32
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    check_format(channels, sample_rate, bits_per_sample)?;
+    let frames = encode_frames(samples, channels, bits_per_sample);
+
+    let mut identification = vec![0x7F];
+    identification.extend_from_slice(b"FLAC");
+    identification.extend_from_slice(&[1, 0]); // Mapping version 1.0
+    identification.extend_from_slice(&1u16.to_be_bytes()); // One more header packet follows
+    identification.extend_from_slice(b"fLaC");
+    identification.extend(metadata_block_header(0, false, 34));
+    identification.extend(stream_info(samples, channels, sample_rate, bits_per_sample, &frames));
+
+    let comment = vorbis_comment();
+    let mut comment_packet = metadata_block_header(4, true, comment.len());
+    comment_packet.extend(comment);
+
+    // The identification packet has the first page to itself, and audio starts on a fresh page.
+    let mut ogg = OggWriter::new();
+    ogg.write_packet(&identification, 0, false);
+    ogg.flush_page(false);
+    ogg.write_packet(&comment_packet, 0, frames.is_empty());
+    ogg.flush_page(false);
+    let channels = channels as usize;
+    let total = samples.len() / channels;
+    for (index, frame) in frames.iter().enumerate() {
+        let granule = ((index + 1) * BLOCK_SIZE).min(total) as u64;
+        ogg.write_packet(frame, granule, index + 1 == frames.len());
+    }
+    Ok(ogg.bytes)
+}
+
+fn check_format(
+    channels: u16, sample_rate: u32, bits_per_sample: u32
+) -> Result<(), Box<dyn Error>> {
+    if !(1..=8).contains(&channels) {
+        return Err(format!("FLAC supports 1 to 8 channels, not {}", channels).into());
+    }
+    if sample_rate == 0 || sample_rate >= 1 << 20 {
+        return Err(format!("FLAC cannot store a sample rate of {} Hz", sample_rate).into());
+    }
+    if !(4..=32).contains(&bits_per_sample) {
+        return Err(format!("FLAC cannot store {}-bit samples", bits_per_sample).into());
+    }
+    Ok(())
+}
+
+fn metadata_block_header(block_type: u8, last: bool, length: usize) -> Vec<u8> {
+    let flag = if last { 0x80 } else { 0 };
+    vec![flag | block_type, (length >> 16) as u8, (length >> 8) as u8, length as u8]
+}
+
+fn stream_info(
+    samples: &[i32], channels: u16, sample_rate: u32, bits_per_sample: u32, frames: &[Vec<u8>]
+) -> Vec<u8> {
+    let min_frame = frames.iter().map(Vec::len).min().unwrap_or(0);
+    let max_frame = frames.iter().map(Vec::len).max().unwrap_or(0);
+    let mut writer = BitWriter::new();
+    writer.write(BLOCK_SIZE as u64, 16);
+    writer.write(BLOCK_SIZE as u64, 16);
+    writer.write(min_frame as u64, 24);
+    writer.write(max_frame as u64, 24);
+    writer.write(sample_rate as u64, 20);
+    writer.write(channels as u64 - 1, 3);
+    writer.write(bits_per_sample as u64 - 1, 5);
+    writer.write((samples.len() / channels as usize) as u64, 36);
+    writer.write(0, 64); // No MD5 signature
+    writer.write(0, 64);
+    writer.into_bytes()
+}
+
+fn vorbis_comment() -> Vec<u8> {
+    let mut bytes = (VENDOR.len() as u32).to_le_bytes().to_vec();
+    bytes.extend_from_slice(VENDOR.as_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes
+}
+
+fn encode_frames(samples: &[i32], channels: u16, bits_per_sample: u32) -> Vec<Vec<u8>> {
+    let channels = channels as usize;
+    let planes: Vec<Vec<i32>> = (0..channels)
+        .map(|channel| samples.iter().skip(channel).step_by(channels).copied().collect())
+        .collect();
+    let total = samples.len() / channels;
+    (0..total.div_ceil(BLOCK_SIZE))
+        .map(|number| {
+            let start = number * BLOCK_SIZE;
+            let end = (start + BLOCK_SIZE).min(total);
+            let blocks: Vec<&[i32]> = planes.iter().map(|plane| &plane[start..end]).collect();
+            encode_frame(number as u64, &blocks, bits_per_sample)
+        })
+        .collect()
+}
+
+fn encode_frame(number: u64, blocks: &[&[i32]], bits_per_sample: u32) -> Vec<u8> {
+    let block_size = blocks[0].len();
+    let mut writer = BitWriter::new();
+    writer.write(0b11_1111_1111_1110, 14); // Sync code
+    writer.write(0, 1);
+    writer.write(0, 1); // Fixed block size, so frames are numbered rather than sample-addressed
+    writer.write(0b0111, 4); // Block size minus one follows as 16 bits
+    writer.write(0b0000, 4); // Sample rate as in STREAMINFO
+    writer.write(blocks.len() as u64 - 1, 4); // Independently coded channels
+    let size_code = match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        _ => 0b000, // As in STREAMINFO
+    };
+    writer.write(size_code, 3);
+    writer.write(0, 1);
+    for byte in utf8_number(number) {
+        writer.write(byte as u64, 8);
+    }
+    writer.write(block_size as u64 - 1, 16);
+    let crc = crc8(&writer.bytes);
+    writer.write(crc as u64, 8);
+
+    for block in blocks {
+        write_subframe(&mut writer, block, bits_per_sample);
+    }
+    writer.align();
+    let crc = crc16(&writer.bytes);
+    writer.write(crc as u64, 16);
+    writer.into_bytes()
+}
+
+/// Writes the cheapest of a constant, fixed-predictor or verbatim subframe.
+fn write_subframe(writer: &mut BitWriter, block: &[i32], bits_per_sample: u32) {
+    if block.iter().all(|&sample| sample == block[0]) {
+        writer.write(0b0000_0000, 8);
+        writer.write_signed(block[0] as i64, bits_per_sample);
+        return;
+    }
+
+    let verbatim_bits = block.len() as u64 * bits_per_sample as u64;
+    let best = (0..=MAX_ORDER.min(block.len() - 1))
+        .map(|order| {
+            let residuals = fixed_residuals(block, order);
+            let (parameter, bits) = best_rice_parameter(&residuals);
+            let total = order as u64 * bits_per_sample as u64 + 10 + bits;
+            (order, residuals, parameter, total)
+        })
+        .min_by_key(|(_, _, _, total)| *total);
+
+    match best {
+        Some((order, residuals, parameter, total)) if total < verbatim_bits => {
+            writer.write(0b0001_0000 | (order as u64) << 1, 8);
+            for &sample in &block[..order] {
+                writer.write_signed(sample as i64, bits_per_sample);
+            }
+            writer.write(0b00, 2); // Rice coding with 4-bit parameters
+            writer.write(0, 4); // A single partition
+            writer.write(parameter as u64, 4);
+            for &residual in &residuals {
+                let folded = zigzag(residual);
+                writer.write_unary(folded >> parameter);
+                writer.write(folded, parameter);
+            }
+        }
+        _ => {
+            writer.write(0b0000_0010, 8);
+            for &sample in block {
+                writer.write_signed(sample as i64, bits_per_sample);
+            }
+        }
+    }
+}
+
+/// Prediction errors of the fixed polynomial predictor of `order` for every sample after the
+/// first `order` warm-up samples.
+fn fixed_residuals(block: &[i32], order: usize) -> Vec<i64> {
+    (order..block.len())
+        .map(|n| {
+            let x = |k: usize| block[n - k] as i64;
+            match order {
+                0 => x(0),
+                1 => x(0) - x(1),
+                2 => x(0) - 2 * x(1) + x(2),
+                3 => x(0) - 3 * x(1) + 3 * x(2) - x(3),
+                _ => x(0) - 4 * x(1) + 6 * x(2) - 4 * x(3) + x(4),
+            }
+        })
+        .collect()
+}
+
+/// The Rice parameter that codes `residuals` in the fewest bits, and that number of bits.
+fn best_rice_parameter(residuals: &[i64]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let bits = residuals
+                .iter()
+                .map(|&residual| (zigzag(residual) >> parameter) + 1 + parameter as u64)
+                .sum();
+            (parameter, bits)
+        })
+        .min_by_key(|(_, bits)| *bits)
+        .unwrap()
+}
+
+/// Interleaves signed values onto the unsigned ones: 0, -1, 1, -2, 2, ...
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// The frame number in the extended UTF-8 coding FLAC uses for it.
+fn utf8_number(value: u64) -> Vec<u8> {
+    if value < 0x80 {
+        return vec![value as u8];
+    }
+    let mut continuation = Vec::new();
+    let mut rest = value;
+    // Each continuation byte carries six bits; the lead byte shrinks by one bit per extra byte.
+    while rest >= 1 << (6 - continuation.len()) {
+        continuation.push(0x80 | (rest & 0x3F) as u8);
+        rest >>= 6;
+    }
+    let count = continuation.len() + 1;
+    let lead = (0xFF00u16 >> count) as u8 | rest as u8;
+    let mut bytes = vec![lead];
+    bytes.extend(continuation.iter().rev());
+    bytes
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+/// Packs values most significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u64,
+    pending_bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { bytes: Vec::new(), pending: 0, pending_bits: 0 }
+    }
+
+    /// Writes the low `count` bits of `value`, for `count` up to 32.
+    fn write(&mut self, value: u64, count: u32) {
+        if count > 32 {
+            self.write(value >> 32, count - 32);
+            self.write(value & 0xFFFF_FFFF, 32);
+            return;
+        }
+        self.pending = (self.pending << count) | (value & ((1u64 << count) - 1));
+        self.pending_bits += count;
+        while self.pending_bits >= 8 {
+            self.pending_bits -= 8;
+            self.bytes.push((self.pending >> self.pending_bits) as u8);
+        }
+        self.pending &= (1u64 << self.pending_bits) - 1;
+    }
+
+    /// Writes a two's complement value in `count` bits.
+    fn write_signed(&mut self, value: i64, count: u32) {
+        self.write(value as u64, count);
+    }
+
+    /// Writes `value` zeros followed by a one.
+    fn write_unary(&mut self, value: u64) {
+        let mut zeros = value;
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    /// Pads with zeros to the next byte boundary.
+    fn align(&mut self) {
+        if self.pending_bits > 0 {
+            self.write(0, 8 - self.pending_bits);
+        }
+    }
+
+    fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A sweep, a burst of full-scale extremes and a stretch of silence, so frames use constant,
+    /// fixed-predictor and verbatim subframes.
+    fn test_samples(channels: u16, bits_per_sample: u32, frames: usize) -> Vec<i32> {
+        let peak = (1 << (bits_per_sample - 1)) - 1;
+        (0..frames * channels as usize)
+            .map(|i| {
+                let (frame, channel) = (i / channels as usize, i % channels as usize);
+                match frame {
+                    0..=4999 => {
+                        let phase = frame as f64 * (0.01 + channel as f64 * 0.003);
+                        (phase.sin() * frame as f64 / 5000.0 * peak as f64) as i32
+                    }
+                    5000..=5999 => if (i * 7919) % 3 == 0 { peak } else { -peak - 1 },
+                    _ => 0,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_with_an_independent_decoder() {
+        for bits_per_sample in [16, 24] {
+            for channels in [1, 2] {
+                // Not a whole number of blocks, so the last frame is short.
+                let samples = test_samples(channels, bits_per_sample, 3 * BLOCK_SIZE + 123);
+                let bytes = encode_flac(&samples, channels, 48_000, bits_per_sample).unwrap();
+
+                let mut reader = claxon::FlacReader::new(Cursor::new(bytes)).unwrap();
+                let info = reader.streaminfo();
+                assert_eq!(
+                    (info.channels, info.sample_rate, info.bits_per_sample),
+                    (channels as u32, 48_000, bits_per_sample)
+                );
+                assert_eq!(info.samples, Some((samples.len() / channels as usize) as u64));
+                let decoded: Vec<i32> = reader.samples().map(Result::unwrap).collect();
+                assert!(
+                    decoded == samples,
+                    "{}-bit {} channel audio changed in the round trip", bits_per_sample, channels
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        assert!(encode_flac(&[], 0, 48_000, 16).is_err());
+        assert!(encode_flac(&[], 9, 48_000, 16).is_err());
+        assert!(encode_flac(&[], 1, 1 << 20, 16).is_err());
+        assert!(encode_flac(&[], 1, 48_000, 33).is_err());
+    }
+}
@@ -1,35 +1,73 @@
+use std::ops::Index;
+
 use rand::Rng;
 
+use crate::bits::{BitSlice, BitVec};
+use crate::genome_schema::{InitialLength, SCHEMA};
+use crate::serialise_genome::FORMAT_VERSION;
+
 pub const PARAMETERS: usize = 5; // Frequency, Amplitude, Duration, Phase
 pub const BITS_PER_PARAMETER: usize = 8;
+/// Extended note genes follow the parameters with an ADSR envelope: four bits each for attack,
+/// decay and release, three for sustain and one for the curve.
+pub const ENVELOPE_BITS: usize = 16;
+/// Stereo note genes follow the envelope with a pan position.
+pub const PAN_BITS: usize = 8;
+/// Effect genes of format version 8 onwards end with their scope: the whole mix, one voice, or a
+/// section of the song.
+pub const EFFECT_SCOPE_BITS: usize = 8;
+/// The timbre chromosome encodes the spectrum of the custom waveform: one chunk per harmonic,
+/// holding its amplitude and phase.
+pub const TIMBRE_HARMONICS: usize = 16;
+pub const BITS_PER_HARMONIC: usize = 8;
+pub const TIMBRE_BITS: usize = TIMBRE_HARMONICS * BITS_PER_HARMONIC;
 
+/// A pair of strands, each a packed `BitVec`.
+#[derive(Clone, Debug)]
 pub struct Chromosome {
-    left_chromosome: Vec<u8>,
-    right_chromosome: Vec<u8>,
+    left_chromosome: BitVec,
+    right_chromosome: BitVec,
 }
 
 impl Chromosome {
-    pub fn new(left_chromosome: Vec<u8>, right_chromosome: Vec<u8>) -> Self {
+    /// Takes each strand as a `BitVec` or as bits stored one to a byte.
+    pub fn new(left_chromosome: impl Into<BitVec>, right_chromosome: impl Into<BitVec>) -> Self {
         Chromosome {
-            left_chromosome,
-            right_chromosome,
+            left_chromosome: left_chromosome.into(),
+            right_chromosome: right_chromosome.into(),
         }
     }
 
-    pub fn get_left_chromosome(&self) -> &[u8] {
-        &self.left_chromosome
+    pub fn get_left_chromosome(&self) -> BitSlice<'_> {
+        self.left_chromosome.as_bits()
     }
 
-    pub fn get_right_chromosome(&self) -> &[u8] {
-        &self.right_chromosome
+    pub fn get_right_chromosome(&self) -> BitSlice<'_> {
+        self.right_chromosome.as_bits()
+    }
+
+    /// How many loci differ between the two chromosomes, left strand against left and right
+    /// against right.
+    pub fn distance(&self, other: &Chromosome) -> usize {
+        self.left_chromosome.hamming_distance(&other.left_chromosome)
+            + self.right_chromosome.hamming_distance(&other.right_chromosome)
     }
 
     pub fn initialise_random_chromosome(min_start_len: i32, max_start_len: i32) -> Self {
-        let mut rng = rand::thread_rng();
+        Self::initialise_random_chromosome_with_rng(
+            min_start_len, max_start_len, &mut rand::thread_rng()
+        )
+    }
+
+    /// Same as `initialise_random_chromosome`, but draws every bit from the supplied generator so
+    /// that a seeded generator always produces the same chromosome.
+    pub fn initialise_random_chromosome_with_rng<R: Rng + ?Sized>(
+        min_start_len: i32, max_start_len: i32, rng: &mut R
+    ) -> Self {
         let chr_length: i32 = rng.gen_range(min_start_len..=max_start_len);
         let left_chromosome = (0..chr_length).map(
             |_| rng.gen_range(0..=1)
-        ).collect::<Vec<u8>>();
+        ).collect::<BitVec>();
         // right should be a copy of the left
         let right_chromosome = left_chromosome.clone();
         Chromosome {
@@ -39,60 +77,94 @@ impl Chromosome {
     }
 }
 
+/// A diploid genome: one `Chromosome` for each entry of the `SCHEMA`, in schema order.
+#[derive(Clone, Debug)]
 pub struct Genome {
-    pub notes: Chromosome,
-    pub effects: Chromosome,
-    pub sine_codon: Chromosome,
-    pub square_codon: Chromosome,
-    pub custom_codon: Chromosome,
-    pub low_pass_codon: Chromosome,
-    pub high_pass_codon: Chromosome,
-    pub reverb_codon: Chromosome,
-    pub echo_codon: Chromosome,
-    pub mutation_rate: Chromosome,
+    chromosomes: Vec<Chromosome>,
+    version: u16,
 }
 
 impl Genome {
-    pub fn new(
-        notes_left: Vec<u8>, notes_right: Vec<u8>,
-        effects_left: Vec<u8>, effects_right: Vec<u8>,
-        sine_codon_left: Vec<u8>, sine_codon_right: Vec<u8>,
-        square_codon_left: Vec<u8>, square_codon_right: Vec<u8>,
-        custom_codon_left: Vec<u8>, custom_codon_right: Vec<u8>,
-        low_pass_codon_left: Vec<u8>, low_pass_codon_right: Vec<u8>,
-        high_pass_codon_left: Vec<u8>, high_pass_codon_right: Vec<u8>,
-        reverb_codon_left: Vec<u8>, reverb_codon_right: Vec<u8>,
-        echo_codon_left: Vec<u8>, echo_codon_right: Vec<u8>,
-        mutation_rate_left: Vec<u8>, mutation_rate_right: Vec<u8>
-    ) -> Self {
-        Genome {
-            notes: Chromosome::new(notes_left, notes_right),
-            effects: Chromosome::new(effects_left, effects_right),
-            sine_codon: Chromosome::new(sine_codon_left, sine_codon_right),
-            square_codon: Chromosome::new(square_codon_left, square_codon_right),
-            custom_codon: Chromosome::new(custom_codon_left, custom_codon_right),
-            low_pass_codon: Chromosome::new(low_pass_codon_left, low_pass_codon_right),
-            high_pass_codon: Chromosome::new(high_pass_codon_left, high_pass_codon_right),
-            reverb_codon: Chromosome::new(reverb_codon_left, reverb_codon_right),
-            echo_codon: Chromosome::new(echo_codon_left, echo_codon_right),
-            mutation_rate: Chromosome::new(mutation_rate_left, mutation_rate_right),
-        }
+    /// Builds a genome from one chromosome per schema entry, in schema order, laid out as this
+    /// build lays out new genomes.
+    pub fn new(chromosomes: Vec<Chromosome>) -> Self {
+        Self::with_version(chromosomes, FORMAT_VERSION)
+    }
+
+    /// Builds a genome whose genes are laid out as in genome files of format `version`, so that
+    /// the decoder reads them as they were read when the file was written.
+    pub fn with_version(chromosomes: Vec<Chromosome>, version: u16) -> Self {
+        assert_eq!(
+            chromosomes.len(),
+            SCHEMA.chromosomes.len(),
+            "a genome needs one chromosome per schema entry"
+        );
+        Genome { chromosomes, version }
+    }
+
+    /// The genome file format version whose gene layout the genome follows: `FORMAT_VERSION`
+    /// for genomes made by this build, or the version of the file a genome was loaded from.
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// The chromosomes, in schema order.
+    pub fn chromosomes(&self) -> &[Chromosome] {
+        &self.chromosomes
+    }
+
+    /// The named chromosome, if the schema has one by that name.
+    pub fn chromosome(&self, name: &str) -> Option<&Chromosome> {
+        SCHEMA.index(name).map(|index| &self.chromosomes[index])
+    }
+
+    /// How many loci differ between the two genomes over all of their chromosomes, a cheap
+    /// measure of how related two songs are.
+    pub fn distance(&self, other: &Genome) -> usize {
+        self.chromosomes
+            .iter()
+            .zip(&other.chromosomes)
+            .map(|(chromosome, other)| chromosome.distance(other))
+            .sum()
     }
 
     pub fn initialise_random_genome(
         large_chr_min: i32, large_chr_max: i32, small_chr_min: i32, small_chr_max: i32
     ) -> Self {
-        Genome {
-            notes: Chromosome::initialise_random_chromosome(large_chr_min, large_chr_max),
-            effects: Chromosome::initialise_random_chromosome(large_chr_min, large_chr_max),
-            sine_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            square_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            custom_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            low_pass_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            high_pass_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            reverb_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            echo_codon: Chromosome::initialise_random_chromosome(small_chr_min, small_chr_max),
-            mutation_rate: Chromosome::initialise_random_chromosome(8, 8),
-        }
+        Self::initialise_random_genome_with_rng(
+            large_chr_min, large_chr_max, small_chr_min, small_chr_max, &mut rand::thread_rng()
+        )
     }
-}
\ No newline at end of file
+
+    /// Same as `initialise_random_genome`, but every chromosome is drawn from the supplied
+    /// generator, in schema order, so a seeded generator gives a bit-identical genome.
+    pub fn initialise_random_genome_with_rng<R: Rng + ?Sized>(
+        large_chr_min: i32, large_chr_max: i32, small_chr_min: i32, small_chr_max: i32,
+        rng: &mut R
+    ) -> Self {
+        let chromosomes = SCHEMA
+            .chromosomes
+            .iter()
+            .map(|spec| {
+                let (min, max) = match spec.length {
+                    InitialLength::Large => (large_chr_min, large_chr_max),
+                    InitialLength::Small => (small_chr_min, small_chr_max),
+                    InitialLength::Fixed(length) => (length as i32, length as i32),
+                };
+                Chromosome::initialise_random_chromosome_with_rng(min, max, rng)
+            })
+            .collect();
+        Genome::new(chromosomes)
+    }
+}
+
+/// The chromosome at a schema position, such as `genome_schema::NOTES`. Every genome has one
+/// chromosome per schema entry, so only a position past the end of the schema panics; use
+/// `Genome::chromosome` to look a chromosome up by name.
+impl Index<usize> for Genome {
+    type Output = Chromosome;
+
+    fn index(&self, position: usize) -> &Chromosome {
+        &self.chromosomes[position]
+    }
+}
@@ -1,83 +1,65 @@
 use rand::Rng;
+use crate::bits::{BitSlice, BitVec};
 use crate::genome::{Genome, Chromosome};
+use crate::genome_schema::{ChromosomeRole, SCHEMA};
 
 pub struct GenomeCrosser;
 
 impl GenomeCrosser {
     pub fn crossover(father: &Genome, mother: &Genome) -> Genome {
-        let mut rng = rand::thread_rng();
-
-        let mutation_rate_father = Self::decode_mutation_rate(
-            &father.mutation_rate.get_left_chromosome()
-        );
-        let mutation_rate_mother = Self::decode_mutation_rate(
-            &mother.mutation_rate.get_left_chromosome()
-        );
-
-        let crossed_notes = Self::cross_chromosomes(
-            &father.notes, &mother.notes, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_effects = Self::cross_chromosomes(
-            &father.effects, &mother.effects, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_sine_codon = Self::cross_chromosomes(
-            &father.sine_codon, &mother.sine_codon, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_square_codon = Self::cross_chromosomes(
-            &father.square_codon, &mother.square_codon, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_custom_codon = Self::cross_chromosomes(
-            &father.custom_codon, &mother.custom_codon, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_low_pass_codon = Self::cross_chromosomes(
-            &father.low_pass_codon, &mother.low_pass_codon, mutation_rate_father,
-            mutation_rate_mother
-        );
-        let crossed_high_pass_codon = Self::cross_chromosomes(
-            &father.high_pass_codon, &mother.high_pass_codon, mutation_rate_father,
-            mutation_rate_mother
-        );
-        let crossed_reverb_codon = Self::cross_chromosomes(
-            &father.reverb_codon, &mother.reverb_codon, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_echo_codon = Self::cross_chromosomes(
-            &father.echo_codon, &mother.echo_codon, mutation_rate_father, mutation_rate_mother
-        );
-        let crossed_mutation_rate = Self::cross_chromosomes(
-            &father.mutation_rate, &mother.mutation_rate, mutation_rate_father,
-            mutation_rate_mother
-        );
+        Self::crossover_with_rng(father, mother, &mut rand::thread_rng())
+    }
 
-        Genome {
-            notes: crossed_notes,
-            effects: crossed_effects,
-            sine_codon: crossed_sine_codon,
-            square_codon: crossed_square_codon,
-            custom_codon: crossed_custom_codon,
-            low_pass_codon: crossed_low_pass_codon,
-            high_pass_codon: crossed_high_pass_codon,
-            reverb_codon: crossed_reverb_codon,
-            echo_codon: crossed_echo_codon,
-            mutation_rate: crossed_mutation_rate,
-        }
+    /// Same as `crossover`, but all recombination and mutation draws come from the supplied
+    /// generator, so a seeded generator always produces the same child from the same parents.
+    ///
+    /// The child keeps the gene layout of the older of its parents' format versions, so the
+    /// offspring of genomes loaded from old files go on sounding the way they did.
+    pub fn crossover_with_rng<R: Rng + ?Sized>(
+        father: &Genome, mother: &Genome, rng: &mut R
+    ) -> Genome {
+        let mutation_rate = |genome: &Genome| {
+            SCHEMA
+                .chromosomes
+                .iter()
+                .position(|spec| spec.role == ChromosomeRole::MutationRate)
+                .map_or(0.0, |index| {
+                    Self::decode_mutation_rate(genome.chromosomes()[index].get_left_chromosome())
+                })
+        };
+        let mutation_rate_father = mutation_rate(father);
+        let mutation_rate_mother = mutation_rate(mother);
+
+        let chromosomes = father
+            .chromosomes()
+            .iter()
+            .zip(mother.chromosomes())
+            .map(|(father_chromosome, mother_chromosome)| {
+                Self::cross_chromosomes(
+                    father_chromosome, mother_chromosome, mutation_rate_father,
+                    mutation_rate_mother, rng
+                )
+            })
+            .collect();
+        Genome::with_version(chromosomes, father.version().min(mother.version()))
     }
 
-    fn cross_chromosomes(
+    fn cross_chromosomes<R: Rng + ?Sized>(
         father_chromosome: &Chromosome, mother_chromosome: &Chromosome, father_mutation_rate: f64,
-        mother_mutation_rate: f64
+        mother_mutation_rate: f64, rng: &mut R
     ) -> Chromosome {
-        let mut rng = rand::thread_rng();
-
         // Cross over the left and right chromosomes of both parents
         let crossed_father = Self::cross_single_chromosome(
-            &father_chromosome.get_left_chromosome(),
-            &father_chromosome.get_right_chromosome(),
-            father_mutation_rate
+            father_chromosome.get_left_chromosome(),
+            father_chromosome.get_right_chromosome(),
+            father_mutation_rate,
+            rng
         );
         let crossed_mother = Self::cross_single_chromosome(
-            &mother_chromosome.get_left_chromosome(),
-            &mother_chromosome.get_right_chromosome(),
-            mother_mutation_rate
+            mother_chromosome.get_left_chromosome(),
+            mother_chromosome.get_right_chromosome(),
+            mother_mutation_rate,
+            rng
         );
 
         // Randomly set the crossed-over chromosomes as left and right
@@ -88,14 +70,12 @@ impl GenomeCrosser {
         }
     }
 
-    fn cross_single_chromosome(
-        first: &[u8], second: &[u8], mutation_rate: f64
-    ) -> Vec<u8> {
-        let mut rng = rand::thread_rng();
-
+    fn cross_single_chromosome<R: Rng + ?Sized>(
+        first: BitSlice, second: BitSlice, mutation_rate: f64, rng: &mut R
+    ) -> BitVec {
         let first_len = first.len();
         let second_len = second.len();
-        let mut child = Vec::with_capacity(first_len.max(second_len));
+        let mut child = BitVec::with_capacity(first_len.max(second_len));
 
         let num_crossovers = rng.gen_range(1..=4);
         let mut crossover_points = Vec::new();
@@ -113,9 +93,9 @@ impl GenomeCrosser {
             let next_pos_second = (crossover_point * second_len as f64).round() as usize;
 
             if in_first {
-                child.extend_from_slice(&first[current_pos_first..next_pos_first]);
+                child.extend_from_bits(first.slice(current_pos_first..next_pos_first));
             } else {
-                child.extend_from_slice(&second[current_pos_second..next_pos_second]);
+                child.extend_from_bits(second.slice(current_pos_second..next_pos_second));
             }
 
             in_first = !in_first;
@@ -124,41 +104,71 @@ impl GenomeCrosser {
         }
 
         if in_first {
-            child.extend_from_slice(&first[current_pos_first..]);
+            child.extend_from_bits(first.slice(current_pos_first..));
         } else {
-            child.extend_from_slice(&second[current_pos_second..]);
+            child.extend_from_bits(second.slice(current_pos_second..));
         }
 
-        Self::apply_mutation(&mut child, mutation_rate);
+        Self::apply_mutation(&mut child, mutation_rate, rng);
 
         child
     }
 
-    fn decode_mutation_rate(bits: &[u8]) -> f64 {
-        let value = bits.iter().rev().enumerate().fold(
-            0, |acc, (i, &bit)| acc + (bit as usize * (1 << i))
-        );
+    /// Reads the rate from the first eight bits of the strand, the length it is created with, so
+    /// however long insertions make it the rate stays within 0.0 to 0.2.
+    fn decode_mutation_rate(bits: BitSlice) -> f64 {
+        let value = bits.slice(..bits.len().min(8)).value();
         value as f64 / (255.0 * 5.0)
     }
 
-    fn apply_mutation(chromosome: &mut Vec<u8>, mutation_rate: f64) {
-        let mut rng = rand::thread_rng();
+    fn apply_mutation<R: Rng + ?Sized>(chromosome: &mut BitVec, mutation_rate: f64, rng: &mut R) {
         let substitution_rate = mutation_rate * 0.8;
         let indel_rate = mutation_rate * 0.1;
-        for bit in chromosome.iter_mut() {
+        for locus in 0..chromosome.len() {
             if rng.gen_bool(substitution_rate) {
-                *bit = 1 - *bit;
+                chromosome.flip(locus);
             }
         }
 
         if rng.gen_bool(indel_rate) {
-            let pos = rng.gen_range(0..chromosome.len());
-            chromosome.insert(pos, rng.gen());
+            let pos = rng.gen_range(0..=chromosome.len());
+            chromosome.insert(pos, rng.gen_range(0..=1));
         }
 
-        if rng.gen_bool(indel_rate) {
+        if rng.gen_bool(indel_rate) && !chromosome.is_empty() {
             let pos = rng.gen_range(0..chromosome.len());
             chromosome.remove(pos);
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::seeded_rng;
+
+    #[test]
+    fn mutation_rate_reads_eight_bits() {
+        let bits = |text: &str| text.bytes().map(|bit| bit - b'0').collect::<BitVec>();
+        assert_eq!(GenomeCrosser::decode_mutation_rate(bits("").as_bits()), 0.0);
+        assert_eq!(GenomeCrosser::decode_mutation_rate(bits("11111111").as_bits()), 0.2);
+        // A strand lengthened by insertions is still read from its first eight bits.
+        let long = bits(&"1".repeat(100));
+        assert_eq!(GenomeCrosser::decode_mutation_rate(long.as_bits()), 0.2);
+    }
+
+    #[test]
+    fn crossover_survives_a_lengthened_mutation_rate_strand() {
+        let mut rng = seeded_rng(3);
+        let genome = Genome::initialise_random_genome_with_rng(64, 128, 4, 8, &mut rng);
+        let position = SCHEMA
+            .chromosomes
+            .iter()
+            .position(|spec| spec.role == ChromosomeRole::MutationRate)
+            .unwrap();
+        let mut chromosomes = genome.chromosomes().to_vec();
+        chromosomes[position] = Chromosome::new(vec![1; 100], vec![1; 100]);
+        let genome = Genome::new(chromosomes);
+        GenomeCrosser::crossover_with_rng(&genome, &genome, &mut rng);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::decode_genome::{EffectType, WaveFunction};
+use crate::genome::TIMBRE_BITS;
+
+/// The chromosomes a genome is made of, in order. Random initialisation, crossover, mutation,
+/// serialisation and the decoder's codon tables all iterate over the schema, so a new voice or
+/// effect needs only an entry here, besides its decoding and synthesis.
+pub struct GenomeSchema {
+    pub chromosomes: &'static [ChromosomeSpec],
+}
+
+/// One chromosome of the schema.
+#[derive(Clone, Copy, Debug)]
+pub struct ChromosomeSpec {
+    /// Identifies the chromosome in saved genomes, to the decoder and to the expression models.
+    pub name: &'static str,
+    pub role: ChromosomeRole,
+    /// How long the chromosome of a random genome is.
+    pub length: InitialLength,
+    /// The first genome file format version that stored the chromosome. Older files get an empty
+    /// chromosome instead, which the decoder treats as carrying no genes.
+    pub since: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChromosomeRole {
+    /// Genes read directly by the decoder: notes, effects, timbre, tempo or dominance.
+    Payload,
+    /// A start codon: wherever its sequence appears in the note or effect chromosome, a note of
+    /// its wave function or an instance of its effect begins.
+    StartCodon(Codon),
+    /// The genome's own mutation rate, read from each parent before crossover.
+    MutationRate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codon {
+    Note(WaveFunction),
+    Effect(EffectType),
+}
+
+/// Length of a chromosome in a random genome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitialLength {
+    /// Within the large range given to `Genome::initialise_random_genome`.
+    Large,
+    /// Within the small range given to `Genome::initialise_random_genome`.
+    Small,
+    Fixed(usize),
+}
+
+const fn spec(
+    name: &'static str, role: ChromosomeRole, length: InitialLength, since: u16
+) -> ChromosomeSpec {
+    ChromosomeSpec { name, role, length, since }
+}
+
+const fn note_codon(
+    name: &'static str, wave_function: WaveFunction, since: u16
+) -> ChromosomeSpec {
+    spec(name, ChromosomeRole::StartCodon(Codon::Note(wave_function)), InitialLength::Small, since)
+}
+
+const fn effect_codon(name: &'static str, effect: EffectType, since: u16) -> ChromosomeSpec {
+    spec(name, ChromosomeRole::StartCodon(Codon::Effect(effect)), InitialLength::Small, since)
+}
+
+/// Positions in the `SCHEMA` of the payload chromosomes, so the decoder can index a `Genome`
+/// directly rather than look chromosomes up by a name that might not exist. They are found by
+/// name when the crate is compiled, so the schema can be reordered freely and a name missing
+/// from it fails the build.
+pub const NOTES: usize = position("notes");
+pub const EFFECTS: usize = position("effects");
+pub const TIMBRE: usize = position("timbre");
+pub const TEMPO: usize = position("tempo");
+pub const DOMINANCE: usize = position("dominance");
+
+const fn position(name: &str) -> usize {
+    match SCHEMA.index(name) {
+        Some(position) => position,
+        None => panic!("the schema has no chromosome by that name"),
+    }
+}
+
+/// The schema every genome follows. Where two start codons match at the same place, the one
+/// listed first wins.
+pub const SCHEMA: GenomeSchema = GenomeSchema {
+    chromosomes: &[
+        spec("notes", ChromosomeRole::Payload, InitialLength::Large, 1),
+        spec("effects", ChromosomeRole::Payload, InitialLength::Large, 1),
+        note_codon("sine_codon", WaveFunction::Sine, 1),
+        note_codon("square_codon", WaveFunction::Square, 1),
+        note_codon("saw_codon", WaveFunction::Saw, 2),
+        note_codon("triangle_codon", WaveFunction::Triangle, 2),
+        note_codon("custom_codon", WaveFunction::Custom, 1),
+        spec("timbre", ChromosomeRole::Payload, InitialLength::Fixed(TIMBRE_BITS), 3),
+        effect_codon("low_pass_codon", EffectType::LowPass, 1),
+        effect_codon("high_pass_codon", EffectType::HighPass, 1),
+        effect_codon("band_pass_codon", EffectType::BandPass, 6),
+        effect_codon("notch_codon", EffectType::Notch, 6),
+        effect_codon("reverb_codon", EffectType::Reverb, 1),
+        effect_codon("echo_codon", EffectType::Echo, 1),
+        effect_codon("distortion_codon", EffectType::Distortion, 7),
+        effect_codon("chorus_codon", EffectType::Chorus, 7),
+        effect_codon("flanger_codon", EffectType::Flanger, 7),
+        effect_codon("bitcrusher_codon", EffectType::Bitcrusher, 7),
+        effect_codon("compressor_codon", EffectType::Compressor, 7),
+        spec("tempo", ChromosomeRole::Payload, InitialLength::Fixed(8), 4),
+        spec("dominance", ChromosomeRole::Payload, InitialLength::Large, 5),
+        spec("mutation_rate", ChromosomeRole::MutationRate, InitialLength::Fixed(8), 1),
+    ],
+};
+
+impl GenomeSchema {
+    /// Position of the named chromosome in the schema. Written out byte by byte so that it can
+    /// run in constants.
+    pub const fn index(&self, name: &str) -> Option<usize> {
+        let name = name.as_bytes();
+        let mut index = 0;
+        'chromosomes: while index < self.chromosomes.len() {
+            let candidate = self.chromosomes[index].name.as_bytes();
+            index += 1;
+            if candidate.len() != name.len() {
+                continue;
+            }
+            let mut byte = 0;
+            while byte < name.len() {
+                if candidate[byte] != name[byte] {
+                    continue 'chromosomes;
+                }
+                byte += 1;
+            }
+            return Some(index - 1);
+        }
+        None
+    }
+
+    /// Positions and targets of the start codons, in schema order.
+    pub fn codons(&self) -> impl Iterator<Item = (usize, Codon)> + '_ {
+        self.chromosomes.iter().enumerate().filter_map(|(index, spec)| match spec.role {
+            ChromosomeRole::StartCodon(codon) => Some((index, codon)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_positions_match_their_names() {
+        let positions = [
+            (NOTES, "notes"), (EFFECTS, "effects"), (TIMBRE, "timbre"), (TEMPO, "tempo"),
+            (DOMINANCE, "dominance"),
+        ];
+        for (position, name) in positions {
+            assert_eq!(SCHEMA.chromosomes[position].name, name);
+            assert_eq!(SCHEMA.chromosomes[position].role, ChromosomeRole::Payload, "{}", name);
+        }
+    }
+
+    #[test]
+    fn finds_chromosomes_by_name() {
+        assert_eq!(SCHEMA.index("mutation_rate"), Some(SCHEMA.chromosomes.len() - 1));
+        assert_eq!(SCHEMA.index("note"), None);
+        assert_eq!(SCHEMA.index("notes_"), None);
+        assert_eq!(SCHEMA.index(""), None);
+    }
+}
@@ -1,13 +1,45 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use rand::Rng;
+use crate::decode_genome::{DecodeConfig, DecodedGenome};
+use crate::genome::Genome;
 use crate::genome_crosser::GenomeCrosser;
 
 #[derive(Clone, Debug)]
 pub struct Song {
-    pub genome: Vec<u8>,
+    pub id: usize,
+    pub genome: Genome,
+    /// Phenotype decoded once when the song is created, so that rating, fitness evaluation and
+    /// playback do not have to decode the genome again.
+    pub decoded: DecodedGenome,
+    pub birth_generation: usize,
+    /// Ids of the father and mother, or `None` for songs placed into the world directly.
+    pub parent_ids: Option<(usize, usize)>,
     pub fitness: f32,
 }
 
+impl Song {
+    pub fn new(
+        id: usize, genome: Genome, birth_generation: usize, parent_ids: Option<(usize, usize)>,
+        decode_config: &DecodeConfig
+    ) -> Self {
+        let decoded = DecodedGenome::decode_with(&genome, decode_config);
+        Song {
+            id,
+            genome,
+            decoded,
+            birth_generation,
+            parent_ids,
+            fitness: 0.0,
+        }
+    }
+
+    /// The song's share in parent selection: its fitness, or zero for a score that is not a
+    /// finite number, so that one broken song cannot stop the world reproducing.
+    fn selection_weight(&self) -> f32 {
+        if self.fitness.is_finite() { self.fitness } else { 0.0 }
+    }
+}
+
 #[derive(Debug)]
 pub struct Node {
     pub id: usize,
@@ -22,20 +54,44 @@ pub struct Edge {
     pub weight: f32,
 }
 
+// Nodes are kept in a `BTreeMap` so that every pass over the world visits them in the same order,
+// which keeps a seeded simulation reproducible.
 #[derive(Debug)]
 pub struct Graph {
-    pub nodes: HashMap<usize, Node>,
+    pub nodes: BTreeMap<usize, Node>,
     pub edges: Vec<Edge>,
+    /// Number of rounds of reproduction the world has been through.
+    pub generation: usize,
+    /// How the genomes of songs in this world are decoded into music.
+    pub decode_config: DecodeConfig,
+    next_song_id: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Graph {
     pub fn new() -> Self {
         Graph {
-            nodes: HashMap::new(),
+            nodes: BTreeMap::new(),
             edges: Vec::new(),
+            generation: 0,
+            decode_config: DecodeConfig::default(),
+            next_song_id: 0,
         }
     }
 
+    /// Wraps a genome in a new song born in the current generation, with a fresh id and no
+    /// parents. Use this to populate the world before the first round of reproduction.
+    pub fn create_song(&mut self, genome: Genome) -> Song {
+        let id = self.next_song_id;
+        self.next_song_id += 1;
+        Song::new(id, genome, self.generation, None, &self.decode_config)
+    }
+
     pub fn add_node(&mut self, id: usize, capacity: usize) {
         self.nodes.insert(id, Node {
             id,
@@ -60,26 +116,40 @@ impl Graph {
         }
     }
 
-    pub fn calculate_migrations(&self) -> HashMap<usize, Vec<usize>> {
-        let mut rng = rand::thread_rng();
-        let mut migrations: HashMap<usize, Vec<usize>> = HashMap::new();
+    pub fn calculate_migrations(&self) -> BTreeMap<usize, Vec<usize>> {
+        self.calculate_migrations_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Same as `calculate_migrations`, but draws from the supplied generator so that a seeded
+    /// world always migrates along the same edges.
+    pub fn calculate_migrations_with_rng<R: Rng + ?Sized>(
+        &self, rng: &mut R
+    ) -> BTreeMap<usize, Vec<usize>> {
+        let mut migrations: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
 
         for edge in &self.edges {
             if rng.gen::<f32>() < edge.weight {
-                migrations.entry(edge.source).or_insert_with(Vec::new).push(edge.destination);
+                migrations.entry(edge.source).or_default().push(edge.destination);
             }
         }
 
         migrations
     }
 
-    pub fn reproduce_songs(&mut self, migrations: &HashMap<usize, Vec<usize>>) {
-        let mut new_generation: HashMap<usize, Vec<Song>> = HashMap::new();
+    pub fn reproduce_songs(&mut self, migrations: &BTreeMap<usize, Vec<usize>>) {
+        self.reproduce_songs_with_rng(migrations, &mut rand::thread_rng());
+    }
+
+    /// Same as `reproduce_songs`, but parent selection, crossover, mutation and the choice of
+    /// migrating children all draw from the supplied generator.
+    pub fn reproduce_songs_with_rng<R: Rng + ?Sized>(
+        &mut self, migrations: &BTreeMap<usize, Vec<usize>>, rng: &mut R
+    ) {
+        let mut new_generation: BTreeMap<usize, Vec<Song>> = BTreeMap::new();
 
         // Handle local reproduction
         for (&node_id, node) in &self.nodes {
-            let mut rng = rand::thread_rng();
-            let total_fitness: f32 = node.songs.iter().map(|s| s.fitness).sum();
+            let total_fitness: f32 = node.songs.iter().map(Song::selection_weight).sum();
             let num_children = node.capacity + migrations.get(&node_id).map_or(0, |v| v.len());
 
             if node.songs.is_empty() || total_fitness == 0.0 {
@@ -87,16 +157,23 @@ impl Graph {
             }
 
             for _ in 0..num_children {
-                let parent1_index = self.select_parent_index(&node.songs, total_fitness, &mut rng);
-                let parent2_index = self.select_parent_index_except(&node.songs, total_fitness, &mut rng, parent1_index);
+                let parent1_index = self.select_parent_index(&node.songs, total_fitness, rng);
+                let parent2_index = self.select_parent_index_except(
+                    &node.songs, total_fitness, rng, parent1_index
+                );
 
                 let parent1 = &node.songs[parent1_index];
                 let parent2 = &node.songs[parent2_index];
 
-                let child_genome = GenomeCrosser::crossover(&parent1.genome, &parent2.genome);
-                new_generation.entry(node_id).or_default().push(
-                    Song { genome: child_genome, fitness: 0.0 }
+                let child_genome = GenomeCrosser::crossover_with_rng(
+                    &parent1.genome, &parent2.genome, rng
                 );
+                let child_id = self.next_song_id;
+                self.next_song_id += 1;
+                new_generation.entry(node_id).or_default().push(Song::new(
+                    child_id, child_genome, self.generation + 1, Some((parent1.id, parent2.id)),
+                    &self.decode_config
+                ));
             }
         }
 
@@ -104,7 +181,6 @@ impl Graph {
         let mut children_to_migrate: Vec<(usize, Song)> = Vec::new();
         for (&source_node, destinations) in migrations {
             if let Some(children) = new_generation.get_mut(&source_node) {
-                let mut rng = rand::thread_rng();
                 for &destination_node in destinations {
                     if !children.is_empty() {
                         let child_index = rng.gen_range(0..children.len());
@@ -126,16 +202,17 @@ impl Graph {
                 node.songs = songs;
             }
         }
+        self.generation += 1;
     }
 
-    fn select_parent_index(
-        &self, songs: &[Song], total_fitness: f32, rng: &mut rand::prelude::ThreadRng
+    fn select_parent_index<R: Rng + ?Sized>(
+        &self, songs: &[Song], total_fitness: f32, rng: &mut R
     ) -> usize {
         let mut cumulative_fitness = 0.0;
         let selection_point = rng.gen_range(0.0..total_fitness);
 
         for (index, song) in songs.iter().enumerate() {
-            cumulative_fitness += song.fitness;
+            cumulative_fitness += song.selection_weight();
             if cumulative_fitness >= selection_point {
                 return index;
             }
@@ -144,18 +221,28 @@ impl Graph {
         songs.len() - 1
     }
 
-    fn select_parent_index_except(
-        &self, songs: &[Song], total_fitness: f32,
-        rng: &mut rand::prelude::ThreadRng, exclude_index: usize
+    fn select_parent_index_except<R: Rng + ?Sized>(
+        &self, songs: &[Song], total_fitness: f32, rng: &mut R, exclude_index: usize
     ) -> usize {
+        // A song alone on its node has no other mate, so it is crossed with itself.
+        if songs.len() == 1 {
+            return exclude_index;
+        }
+        let remaining_fitness = total_fitness - songs[exclude_index].selection_weight();
+        if remaining_fitness <= 0.0 {
+            // Every other song scored zero, so any of them is as good a mate as the next.
+            let index = rng.gen_range(0..songs.len() - 1);
+            return if index >= exclude_index { index + 1 } else { index };
+        }
+
         let mut cumulative_fitness = 0.0;
-        let selection_point = rng.gen_range(0.0..total_fitness - songs[exclude_index].fitness);
+        let selection_point = rng.gen_range(0.0..remaining_fitness);
 
         for (index, song) in songs.iter().enumerate() {
             if index == exclude_index {
                 continue;
             }
-            cumulative_fitness += song.fitness;
+            cumulative_fitness += song.selection_weight();
             if cumulative_fitness >= selection_point {
                 return index;
             }
@@ -0,0 +1,26 @@
+pub mod bits;
+pub mod genome;
+pub mod genome_schema;
+pub mod decode_genome;
+pub mod envelope;
+pub mod expression;
+pub mod pitch;
+pub mod rhythm;
+pub mod play_genes;
+pub mod playback;
+pub mod midi;
+pub mod export;
+pub mod flac;
+mod ogg;
+pub mod vorbis;
+pub mod render;
+pub mod stream;
+pub mod effects;
+pub mod mastering;
+pub mod oscillator;
+pub mod genome_crosser;
+pub mod rng;
+pub mod serialise_genome;
+pub mod graph;
+pub mod evolution;
+pub mod fitness;
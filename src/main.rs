@@ -1,45 +1,58 @@
-mod genome;
-mod decode_genome;
-mod play_genes;
-mod genome_crosser;
-
-use genome::Genome;
-use decode_genome::DecodedGenome;
-use play_genes::play_genes;
-use genome_crosser::GenomeCrosser;
+use std::time::Duration;
+use music_evo::evolution::{Evolution, StopCondition};
+use music_evo::fitness::{
+    score, ClippingPenalty, Consonance, RhythmicRegularity, SilencePenalty, TargetDuration,
+    WeightedFitness,
+};
+use music_evo::graph::Graph;
+use music_evo::play_genes::play_genes;
 use rand::Rng;
 
 fn main() {
-    // Generate random parent genomes. Randomly assign 0s and 1s to the genome.
-    let father_genome = Genome::initialise_random_genome(
-        128, 256, 6, 8
-    );
-    let decoded_father_genome = DecodedGenome::decode(
-        &father_genome
-    );
-    let mother_genome = Genome::initialise_random_genome(
-        128, 256, 6, 8
-    );
-    let decoded_mother_genome = DecodedGenome::decode(
-        &mother_genome
-    );
+    // An optional seed can be passed as the first argument to reproduce an earlier run.
+    let seed = std::env::args()
+        .nth(1)
+        .map(|arg| arg.parse::<u64>().expect("seed must be an unsigned integer"))
+        .unwrap_or_else(|| rand::thread_rng().gen());
+    println!("Seed: {}", seed);
 
-    let child_genome = GenomeCrosser::crossover(
-        &father_genome, &mother_genome
-    );
-
-    // Decode the genome
-    let decoded_genome = DecodedGenome::decode(
-        &child_genome
-    );
+    // Two nodes exchanging the occasional migrant.
+    let mut world = Graph::new();
+    world.add_node(0, 8);
+    world.add_node(1, 8);
+    world.add_edge(0, 1, 0.2);
+    world.add_edge(1, 0, 0.2);
 
-    println!("Father phenotype:");
-    play_genes(&decoded_father_genome).unwrap();
+    let mut fitness = WeightedFitness::new();
+    fitness.add(1.0, Consonance);
+    fitness.add(1.0, RhythmicRegularity);
+    fitness.add(1.0, TargetDuration {
+        target: Duration::from_secs(8),
+        tolerance: Duration::from_secs(6),
+    });
+    fitness.add(0.5, SilencePenalty { threshold: 0.01 });
+    fitness.add(0.5, ClippingPenalty);
 
-    println!("Mother phenotype:");
-    play_genes(&decoded_mother_genome).unwrap();
+    let mut evolution = Evolution::new(
+        world, seed, move |song| score(&fitness, &song.decoded)
+    );
+    evolution.seed_nodes(128, 256, 6, 8);
+    evolution.stop_when(StopCondition::Generations(20));
+    evolution.on_generation(|report, _| {
+        println!(
+            "Generation {}: best fitness {:.3}, mean fitness {:.3}",
+            report.generation, report.best_fitness, report.mean_fitness
+        );
+    });
+    let report = evolution.run();
 
-    // Play the decoded child genome
-    println!("Child phenotype:");
-    play_genes(&decoded_genome).unwrap();
+    // Play the fittest song of the final generation.
+    let best = evolution.graph.nodes
+        .values()
+        .flat_map(|node| node.songs.iter())
+        .find(|song| Some(song.id) == report.best_song_id);
+    if let Some(song) = best {
+        println!("Fittest song (id {}):", song.id);
+        play_genes(&song.decoded).unwrap();
+    }
 }
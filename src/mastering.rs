@@ -0,0 +1,271 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::render::AudioBuffer;
+use crate::rng::seeded_rng;
+
+/// Settings for the mastering stage that `render` applies to the finished mix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MasteringConfig {
+    pub normalisation: Normalisation,
+    /// Peak level, in dBFS, that the soft limiter keeps the output below, or `None` to leave
+    /// peaks alone.
+    pub limiter_ceiling: Option<f32>,
+    /// Whether to add TPDF dither when samples are quantised to 16 bits for export.
+    pub dither: bool,
+}
+
+impl Default for MasteringConfig {
+    /// Loudness-normalised to -16 LUFS, limited at -1 dBFS and dithered, so every song is played
+    /// back at about the same loudness without clipping.
+    fn default() -> Self {
+        MasteringConfig {
+            normalisation: Normalisation::Loudness { target_lufs: -16.0 },
+            limiter_ceiling: Some(-1.0),
+            dither: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalisation {
+    None,
+    /// Scale the mix so its highest peak sits at `target_db` dBFS.
+    Peak { target_db: f32 },
+    /// Scale the mix so its integrated loudness, measured with K-weighting and gating as in
+    /// ITU-R BS.1770, is `target_lufs`.
+    Loudness { target_lufs: f32 },
+}
+
+/// Normalises and then limits `buffer` in place. Silent buffers are left as they are.
+pub fn master(buffer: &mut AudioBuffer, config: &MasteringConfig) {
+    let gain = match config.normalisation {
+        Normalisation::None => None,
+        Normalisation::Peak { target_db } => {
+            let peak = buffer.samples.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            (peak > 0.0).then(|| db_to_gain(target_db) / peak)
+        }
+        Normalisation::Loudness { target_lufs } => {
+            integrated_loudness(buffer).map(|lufs| db_to_gain(target_lufs - lufs))
+        }
+    };
+    if let Some(gain) = gain {
+        for sample in buffer.samples.iter_mut() {
+            *sample *= gain;
+        }
+    }
+
+    if let Some(ceiling) = config.limiter_ceiling {
+        for sample in buffer.samples.iter_mut() {
+            *sample = soft_limit(*sample, ceiling);
+        }
+    }
+}
+
+/// Quantises samples in [-1.0, 1.0] to 16 bits, optionally with triangular dither of one least
+/// significant bit either way. The dither noise is seeded, so the same mix always quantises to
+/// the same samples.
+pub fn quantise_i16(samples: &[f32], dither: bool) -> Vec<i16> {
+    let mut rng = seeded_rng(0);
+    samples
+        .iter()
+        .map(|&sample| {
+            let mut value = sample * i16::MAX as f32;
+            if dither {
+                value += rng.gen::<f32>() - rng.gen::<f32>();
+            }
+            value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        })
+        .collect()
+}
+
+/// Integrated loudness of the buffer in LUFS, or `None` if it is silent. Each channel is
+/// weighted equally.
+pub fn integrated_loudness(buffer: &AudioBuffer) -> Option<f32> {
+    let channels = buffer.channels.max(1) as usize;
+    let frames = buffer.frames();
+    if frames == 0 {
+        return None;
+    }
+
+    // Mean square of the K-weighted signal, summed over channels, for 400 ms blocks every 100 ms.
+    let weighted: Vec<Vec<f64>> = (0..channels)
+        .map(|channel| {
+            let mut filter = KWeighting::new(buffer.sample_rate as f64);
+            buffer.channel(channel as u16).map(|sample| filter.process(sample as f64)).collect()
+        })
+        .collect();
+    let block = ((buffer.sample_rate as f64 * 0.4) as usize).clamp(1, frames);
+    let step = ((buffer.sample_rate as f64 * 0.1) as usize).max(1);
+    let mut powers = Vec::new();
+    let mut start = 0;
+    while start + block <= frames {
+        let power: f64 = weighted
+            .iter()
+            .map(|channel| channel[start..start + block].iter().map(|x| x * x).sum::<f64>())
+            .sum::<f64>()
+            / block as f64;
+        powers.push(power);
+        start += step;
+    }
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = powers
+            .iter()
+            .copied()
+            .filter(|&power| power > 0.0 && loudness(power) > threshold)
+            .collect();
+        (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+    };
+    // An absolute gate at -70 LUFS, then a relative gate 10 LU below what passes it.
+    let absolute = gated_mean(-70.0)?;
+    let relative = gated_mean(loudness(absolute) - 10.0)?;
+    Some(loudness(relative) as f32)
+}
+
+fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Passes samples unchanged up to half the ceiling, then bends them smoothly towards the ceiling
+/// so that peaks are rounded off rather than clipped. `ceiling_db` is in dBFS.
+pub(crate) fn soft_limit(sample: f32, ceiling_db: f32) -> f32 {
+    let ceiling = db_to_gain(ceiling_db);
+    let knee = ceiling * 0.5;
+    let magnitude = sample.abs();
+    if magnitude <= knee {
+        return sample;
+    }
+    let headroom = ceiling - knee;
+    let limited = knee + headroom * ((magnitude - knee) / headroom).tanh();
+    limited.copysign(sample)
+}
+
+/// The two-stage K-weighting filter of ITU-R BS.1770: a high shelf modelling the head followed
+/// by a high-pass, with coefficients worked out for any sample rate.
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let shelf = {
+            let k = (PI * 1681.974450955533 / sample_rate).tan();
+            let q = 0.7071752369554196;
+            let vh = 10f64.powf(3.999843853973347 / 20.0);
+            let vb = vh.powf(0.4996667741545416);
+            let a0 = 1.0 + k / q + k * k;
+            Biquad::new(
+                [
+                    (vh + vb * k / q + k * k) / a0,
+                    2.0 * (k * k - vh) / a0,
+                    (vh - vb * k / q + k * k) / a0,
+                ],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+        let high_pass = {
+            let k = (PI * 38.13547087602444 / sample_rate).tan();
+            let q = 0.5003270373238773;
+            let a0 = 1.0 + k / q + k * k;
+            Biquad::new(
+                [1.0, -2.0, 1.0],
+                [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            )
+        };
+        KWeighting { stages: [shelf, high_pass] }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages.iter_mut().fold(sample, |x, stage| stage.process(x))
+    }
+}
+
+/// A second-order IIR section in transposed direct form II, with `a0` normalised to one.
+#[derive(Clone)]
+pub(crate) struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub(crate) fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, z1: 0.0, z2: 0.0 }
+    }
+
+    pub(crate) fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z1;
+        self.z1 = self.b[1] * x - self.a[0] * y + self.z2;
+        self.z2 = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 48_000;
+
+    fn sine(frequency: f32, amplitude: f32, seconds: f32) -> AudioBuffer {
+        let frames = (seconds * RATE as f32) as usize;
+        let step = 2.0 * std::f32::consts::PI * frequency / RATE as f32;
+        let samples = (0..frames).map(|i| amplitude * (step * i as f32).sin()).collect();
+        AudioBuffer { samples, sample_rate: RATE, channels: 1 }
+    }
+
+    #[test]
+    fn full_scale_sine_measures_minus_three_lufs() {
+        // The reference level of ITU-R BS.1770: a 0 dBFS sine at 1 kHz in one channel.
+        let lufs = integrated_loudness(&sine(1000.0, 1.0, 3.0)).unwrap();
+        assert!((lufs + 3.01).abs() < 0.1, "{}", lufs);
+        assert_eq!(integrated_loudness(&sine(1000.0, 0.0, 3.0)), None);
+    }
+
+    #[test]
+    fn loudness_normalisation_reaches_the_target() {
+        for amplitude in [0.01, 0.1, 0.9] {
+            let mut buffer = sine(1000.0, amplitude, 3.0);
+            master(&mut buffer, &MasteringConfig::default());
+            let lufs = integrated_loudness(&buffer).unwrap();
+            assert!((lufs + 16.0).abs() < 0.5, "{} from amplitude {}", lufs, amplitude);
+        }
+    }
+
+    #[test]
+    fn soft_limit_stays_under_the_ceiling() {
+        for ceiling_db in [-6.0, -1.0, 0.0] {
+            let ceiling = db_to_gain(ceiling_db);
+            let mut previous = f32::NEG_INFINITY;
+            for step in -4000..=4000 {
+                let sample = step as f32 / 100.0;
+                let limited = soft_limit(sample, ceiling_db);
+                assert!(limited.abs() <= ceiling, "{} became {}", sample, limited);
+                assert!(limited >= previous, "not monotonic at {}", sample);
+                if sample.abs() <= ceiling * 0.5 {
+                    assert_eq!(limited, sample);
+                }
+                previous = limited;
+            }
+            assert!(soft_limit(1e30, ceiling_db) <= ceiling);
+        }
+    }
+
+    #[test]
+    fn quantisation_is_deterministic() {
+        let samples = sine(440.0, 0.5, 0.1).samples;
+        assert_eq!(quantise_i16(&samples, true), quantise_i16(&samples, true));
+
+        let plain = quantise_i16(&samples, false);
+        let dithered = quantise_i16(&samples, true);
+        assert_ne!(plain, dithered);
+        // Dither moves a sample by at most one step.
+        assert!(plain.iter().zip(&dithered).all(|(a, b)| (*a as i32 - *b as i32).abs() <= 1));
+        let extremes = quantise_i16(&[-2.0, -1.0, 0.0, 1.0, 2.0], false);
+        assert_eq!(extremes, [i16::MIN, -i16::MAX, 0, i16::MAX, i16::MAX]);
+    }
+}
@@ -0,0 +1,275 @@
+use std::error::Error;
+
+use crate::decode_genome::{DecodedGenome, DecodedParameters, WaveFunction};
+use crate::pitch::frequency_to_midi;
+use crate::rhythm::DEFAULT_BPM;
+
+/// Resolution of the exported file, in ticks per crotchet.
+pub const TICKS_PER_QUARTER: u16 = 480;
+
+/// Pitch bend range, in semitones either way, set on every channel before any notes.
+const BEND_RANGE: f32 = 2.0;
+
+/// Track layout: each wave function gets its own track, MIDI channel and General MIDI program.
+const VOICES: [(WaveFunction, &str, u8); 5] = [
+    (WaveFunction::Sine, "Sine", 79),         // Ocarina
+    (WaveFunction::Square, "Square", 80),     // Lead 1 (square)
+    (WaveFunction::Saw, "Saw", 81),           // Lead 2 (sawtooth)
+    (WaveFunction::Triangle, "Triangle", 73), // Flute
+    (WaveFunction::Custom, "Custom", 16),     // Drawbar Organ
+];
+
+/// Writes the decoded song to a Standard MIDI File.
+pub fn generate_midi(decoded: &DecodedGenome, filename: &str) -> Result<(), Box<dyn Error>> {
+    std::fs::write(filename, generate_midi_data(decoded))?;
+    Ok(())
+}
+
+/// Encodes the decoded song as a type 1 Standard MIDI File.
+///
+/// The first track holds the tempo, followed by one track per wave function used, each on its
+/// own channel with a matching General MIDI program. Every note is written as the nearest MIDI
+/// note plus a pitch bend for the remainder, so tunings off the equal-tempered grid survive.
+/// Pitch bend applies to a whole channel, so notes that overlap on one track share the bend of
+/// the latest to start. Amplitude becomes velocity, and notes that are silent or outside the
+/// MIDI range are left out.
+pub fn generate_midi_data(decoded: &DecodedGenome) -> Vec<u8> {
+    // Songs decoded without a grid keep their exact note timing; the tempo only decides where
+    // the DAW draws their bar lines.
+    let bpm = decoded.tempo.unwrap_or(DEFAULT_BPM);
+    let ticks_per_second = bpm / 60.0 * TICKS_PER_QUARTER as f32;
+
+    let mut tracks = vec![tempo_track(bpm)];
+    for (channel, (wave_function, name, program)) in VOICES.iter().enumerate() {
+        let notes: Vec<&DecodedParameters> = decoded
+            .notes
+            .iter()
+            .filter(|note| note.wave_function.unwrap_or(WaveFunction::Sine) == *wave_function)
+            .collect();
+        if !notes.is_empty() {
+            tracks.push(voice_track(&notes, name, channel as u8, *program, ticks_per_second));
+        }
+    }
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"MThd");
+    bytes.extend_from_slice(&6u32.to_be_bytes());
+    bytes.extend_from_slice(&1u16.to_be_bytes());
+    bytes.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+    for track in tracks {
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        bytes.extend(track);
+    }
+    bytes
+}
+
+/// A MIDI event at an absolute tick. `order` breaks ties so that, at the same tick, note-offs
+/// come before pitch bends, and pitch bends before the note-ons they belong to.
+struct Event {
+    tick: u32,
+    order: u8,
+    data: Vec<u8>,
+}
+
+fn tempo_track(bpm: f32) -> Vec<u8> {
+    let micros_per_quarter = (60_000_000.0 / bpm.max(1.0)).round() as u32;
+    let events = vec![
+        Event { tick: 0, order: 0, data: meta(0x03, b"Tempo") },
+        Event { tick: 0, order: 0, data: meta(0x51, &micros_per_quarter.to_be_bytes()[1..]) },
+    ];
+    encode_track(events)
+}
+
+fn voice_track(
+    notes: &[&DecodedParameters], name: &str, channel: u8, program: u8, ticks_per_second: f32
+) -> Vec<u8> {
+    let mut events = vec![
+        Event { tick: 0, order: 0, data: meta(0x03, name.as_bytes()) },
+        Event { tick: 0, order: 0, data: vec![0xC0 | channel, program] },
+    ];
+    // Registered parameter 0 sets the pitch bend range in semitones and cents.
+    for (controller, value) in [
+        (101, 0), (100, 0), (6, BEND_RANGE as u8), (38, 0), (101, 127), (100, 127),
+    ] {
+        events.push(Event { tick: 0, order: 0, data: vec![0xB0 | channel, controller, value] });
+    }
+
+    for note in notes {
+        if note.frequency <= 0.0 || note.amplitude <= 0.0 {
+            continue;
+        }
+        let pitch = frequency_to_midi(note.frequency);
+        let key = pitch.round();
+        if !(0.0..=127.0).contains(&key) {
+            continue;
+        }
+        let bend = (8192.0 + (pitch - key) / BEND_RANGE * 8192.0)
+            .round()
+            .clamp(0.0, 16383.0) as u16;
+        // Amplitudes run up to just under 2.0.
+        let velocity = (note.amplitude / 2.0 * 127.0).round().clamp(1.0, 127.0) as u8;
+        let start = (note.start_time.as_secs_f32() * ticks_per_second).round() as u32;
+        let end = ((note.start_time + note.duration).as_secs_f32() * ticks_per_second)
+            .round() as u32;
+
+        events.push(Event {
+            tick: start,
+            order: 1,
+            data: vec![0xE0 | channel, (bend & 0x7F) as u8, (bend >> 7) as u8],
+        });
+        events.push(Event {
+            tick: start,
+            order: 2,
+            data: vec![0x90 | channel, key as u8, velocity],
+        });
+        events.push(Event {
+            tick: end.max(start + 1),
+            order: 0,
+            data: vec![0x80 | channel, key as u8, 0],
+        });
+    }
+    encode_track(events)
+}
+
+/// Sorts events into time order and writes them with delta times, ending the track.
+fn encode_track(mut events: Vec<Event>) -> Vec<u8> {
+    events.sort_by_key(|event| (event.tick, event.order));
+    let mut bytes = Vec::new();
+    let mut last_tick = 0;
+    for event in &events {
+        write_variable_length(&mut bytes, event.tick - last_tick);
+        bytes.extend_from_slice(&event.data);
+        last_tick = event.tick;
+    }
+    write_variable_length(&mut bytes, 0);
+    bytes.extend_from_slice(&meta(0x2F, &[]));
+    bytes
+}
+
+fn meta(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0xFF, kind];
+    write_variable_length(&mut bytes, data.len() as u32);
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// Writes a MIDI variable-length quantity: seven bits per byte, most significant first, with the
+/// top bit set on every byte but the last.
+fn write_variable_length(bytes: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::decode_genome::Timbre;
+    use crate::envelope::Envelope;
+
+    /// Each event's absolute tick and bytes.
+    type Track = Vec<(u32, Vec<u8>)>;
+
+    /// The events of one track chunk, each with its absolute tick.
+    fn parse_track(mut bytes: &[u8]) -> Track {
+        let read_variable_length = |bytes: &mut &[u8]| {
+            let mut value = 0;
+            loop {
+                let byte = bytes[0];
+                *bytes = &bytes[1..];
+                value = value << 7 | (byte & 0x7F) as u32;
+                if byte & 0x80 == 0 {
+                    return value;
+                }
+            }
+        };
+        let mut events = Vec::new();
+        let mut tick = 0;
+        while !bytes.is_empty() {
+            tick += read_variable_length(&mut bytes);
+            let length = match bytes[0] {
+                0xFF => {
+                    let mut data = &bytes[2..];
+                    let length = read_variable_length(&mut data) as usize;
+                    bytes.len() - data.len() + length
+                }
+                status if status & 0xF0 == 0xC0 || status & 0xF0 == 0xD0 => 2,
+                _ => 3,
+            };
+            events.push((tick, bytes[..length].to_vec()));
+            bytes = &bytes[length..];
+        }
+        events
+    }
+
+    /// The header fields and the events of every track.
+    fn parse(bytes: &[u8]) -> (u16, u16, Vec<Track>) {
+        assert_eq!(&bytes[..8], b"MThd\0\0\0\x06");
+        let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+        let (format, count, division) = (word(8), word(10), word(12));
+        let mut tracks = Vec::new();
+        let mut rest = &bytes[14..];
+        for _ in 0..count {
+            assert_eq!(&rest[..4], b"MTrk");
+            let length = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let events = parse_track(&rest[8..8 + length]);
+            assert_eq!(events.last().unwrap().1, [0xFF, 0x2F, 0x00]);
+            tracks.push(events);
+            rest = &rest[8 + length..];
+        }
+        assert!(rest.is_empty());
+        (format, division, tracks)
+    }
+
+    fn note(
+        wave_function: WaveFunction, semitones: f32, start: f32, length: f32
+    ) -> DecodedParameters {
+        DecodedParameters {
+            start_time: Duration::from_secs_f32(start),
+            frequency: 440.0 * 2f32.powf(semitones / 12.0),
+            amplitude: 1.0,
+            duration: Duration::from_secs_f32(length),
+            phase: 0.0,
+            wave_function: Some(wave_function),
+            envelope: Envelope::default(),
+            pan: 0.0,
+        }
+    }
+
+    #[test]
+    fn writes_notes_between_semitones_with_a_bend() {
+        let decoded = DecodedGenome {
+            notes: vec![
+                note(WaveFunction::Sine, 0.0, 0.0, 0.25),
+                // A quarter of a semitone above A4, 1.5 s in at 120 bpm: tick 1440.
+                note(WaveFunction::Square, 0.25, 1.5, 0.5),
+            ],
+            effects: Vec::new(),
+            timbre: Timbre::default(),
+            tempo: Some(120.0),
+        };
+        let (format, division, tracks) = parse(&generate_midi_data(&decoded));
+        assert_eq!((format, division), (1, TICKS_PER_QUARTER));
+        assert_eq!(tracks.len(), 3);
+        assert!(tracks[0].contains(&(0, vec![0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20])));
+
+        // The square wave is on channel 1. A quarter semitone up within a two-semitone range
+        // bends by an eighth of 8192, and amplitude 1.0 is half of full velocity.
+        let square = &tracks[2];
+        assert!(square.contains(&(0, vec![0xC1, 80])));
+        let bend = 8192 + 1024u16;
+        let notes: Vec<_> = square.iter().filter(|(_, data)| data[0] & 0xF0 != 0xB0).collect();
+        assert_eq!(notes[2..5], [
+            &(1440, vec![0xE1, (bend & 0x7F) as u8, (bend >> 7) as u8]),
+            &(1440, vec![0x91, 69, 64]),
+            &(1920, vec![0x81, 69, 0]),
+        ]);
+    }
+}
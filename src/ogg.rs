@@ -0,0 +1,109 @@
+/// Serial number of the single logical stream in Ogg output.
+const OGG_SERIAL: u32 = 0x6D65_766F;
+
+/// Pages are closed once they hold this many bytes of packet data.
+const PAGE_TARGET: usize = 4096;
+
+/// Splits packets into the pages of one logical Ogg stream. Small packets share a page until it
+/// reaches `PAGE_TARGET` bytes; large ones continue over as many pages as they need.
+pub(crate) struct OggWriter {
+    pub bytes: Vec<u8>,
+    sequence: u32,
+    first: bool,
+    /// Lacing values and data of the page being filled.
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    /// Granule position of the last packet to end on the page being filled, if any has.
+    granule: Option<u64>,
+    /// The page being filled starts by continuing a packet from the page before.
+    continued: bool,
+}
+
+impl OggWriter {
+    pub fn new() -> Self {
+        OggWriter {
+            bytes: Vec::new(),
+            sequence: 0,
+            first: true,
+            segments: Vec::new(),
+            data: Vec::new(),
+            granule: None,
+            continued: false,
+        }
+    }
+
+    /// Adds `packet` to the stream. `granule` is the number of samples per channel decoded once
+    /// the packet is complete. The last packet closes the stream.
+    pub fn write_packet(&mut self, packet: &[u8], granule: u64, last: bool) {
+        // Lacing values: runs of 255 ended by a shorter value, which is 0 if the packet length is
+        // a multiple of 255.
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+
+        let mut offset = 0;
+        for (index, &segment) in lacing.iter().enumerate() {
+            if self.segments.len() == 255 {
+                self.flush_page(false);
+                // The packet goes on over the next page unless it ended exactly at this one.
+                self.continued = index > 0;
+            }
+            self.segments.push(segment);
+            self.data.extend_from_slice(&packet[offset..offset + segment as usize]);
+            offset += segment as usize;
+        }
+        self.granule = Some(granule);
+
+        if last {
+            self.flush_page(true);
+        } else if self.data.len() >= PAGE_TARGET {
+            self.flush_page(false);
+        }
+    }
+
+    /// Closes the page being filled, so the next packet starts a page of its own.
+    pub fn flush_page(&mut self, last: bool) {
+        if self.segments.is_empty() {
+            return;
+        }
+        let mut header_type = 0;
+        if self.continued {
+            header_type |= 0x01;
+        }
+        if self.first {
+            header_type |= 0x02;
+        }
+        if last {
+            header_type |= 0x04;
+        }
+        // Pages on which no packet ends carry a granule position of -1.
+        let position = self.granule.take().unwrap_or(u64::MAX);
+
+        let mut page = b"OggS".to_vec();
+        page.push(0);
+        page.push(header_type);
+        page.extend_from_slice(&position.to_le_bytes());
+        page.extend_from_slice(&OGG_SERIAL.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(self.segments.len() as u8);
+        page.append(&mut self.segments);
+        page.append(&mut self.data);
+        let crc = ogg_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        self.bytes.extend(page);
+
+        self.continued = false;
+        self.first = false;
+        self.sequence += 1;
+    }
+}
+
+fn ogg_crc(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |mut crc, &byte| {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+        }
+        crc
+    })
+}
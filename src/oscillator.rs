@@ -0,0 +1,70 @@
+use std::f32::consts::PI;
+
+use crate::decode_genome::{Timbre, WaveFunction};
+
+/// The triangle's odd harmonics fall off with the square of their number, so a handful is
+/// indistinguishable from the full series.
+const TRIANGLE_HARMONICS: usize = 16;
+
+/// Returns one sample of the waveform, between roughly -1.0 and 1.0.
+///
+/// `phase` is the position within the current cycle, from 0.0 to 1.0, and `phase_increment` is
+/// how far the phase moves per sample (frequency divided by sample rate). The increment is what
+/// lets the square and saw waves be band-limited: their discontinuities are smoothed with
+/// PolyBLEP residuals, and the additive waveforms drop any harmonic above the Nyquist frequency.
+/// The custom waveform plays the harmonics of `timbre`.
+pub fn oscillate(
+    wave_function: WaveFunction, timbre: &Timbre, phase: f32, phase_increment: f32
+) -> f32 {
+    match wave_function {
+        WaveFunction::Sine => (2.0 * PI * phase).sin(),
+        WaveFunction::Square => {
+            let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+            naive + poly_blep(phase, phase_increment)
+                - poly_blep((phase + 0.5).fract(), phase_increment)
+        }
+        WaveFunction::Saw => 2.0 * phase - 1.0 - poly_blep(phase, phase_increment),
+        WaveFunction::Triangle => {
+            let mut value = 0.0;
+            for k in 0..TRIANGLE_HARMONICS {
+                let harmonic = (2 * k + 1) as f32;
+                if harmonic * phase_increment >= 0.5 {
+                    break;
+                }
+                let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                value += sign * (2.0 * PI * harmonic * phase).sin() / (harmonic * harmonic);
+            }
+            value * 8.0 / (PI * PI)
+        }
+        WaveFunction::Custom => {
+            // Normalised by the summed amplitudes so every timbre peaks at about the same level.
+            let mut value = 0.0;
+            let mut total_amplitude = 0.0;
+            for (n, partial) in timbre.harmonics.iter().enumerate() {
+                let harmonic = (n + 1) as f32;
+                if harmonic * phase_increment >= 0.5 {
+                    break;
+                }
+                value += partial.amplitude * (2.0 * PI * harmonic * phase + partial.phase).sin();
+                total_amplitude += partial.amplitude;
+            }
+            if total_amplitude > 0.0 { value / total_amplitude } else { 0.0 }
+        }
+    }
+}
+
+/// Polynomial band-limited step residual, added around each discontinuity of a naive waveform to
+/// suppress aliasing.
+fn poly_blep(phase: f32, phase_increment: f32) -> f32 {
+    if phase_increment <= 0.0 {
+        0.0
+    } else if phase < phase_increment {
+        let t = phase / phase_increment;
+        t + t - t * t - 1.0
+    } else if phase > 1.0 - phase_increment {
+        let t = (phase - 1.0) / phase_increment;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
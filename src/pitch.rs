@@ -0,0 +1,243 @@
+/// Frequency of A4 (MIDI note 69), the reference for every tuning.
+pub const CONCERT_PITCH: f32 = 440.0;
+
+/// How the frequency gene of a note is turned into Hz.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PitchMapping {
+    /// The original mapping: the gene value times 5 Hz, giving 0 to 1275 Hz in 5 Hz steps.
+    Legacy,
+    /// The gene value picks one of the pitches of `scale` in `key` between two MIDI notes
+    /// (inclusive), spread evenly across the gene's range.
+    Musical {
+        lowest_note: u8,
+        highest_note: u8,
+        /// Pitch class of the tonic, 0 for C up to 11 for B.
+        key: u8,
+        scale: Scale,
+        tuning: Tuning,
+    },
+}
+
+impl Default for PitchMapping {
+    /// C major over three octaves from C3 to C6, in equal temperament.
+    fn default() -> Self {
+        PitchMapping::Musical {
+            lowest_note: 48,
+            highest_note: 84,
+            key: 0,
+            scale: Scale::Major,
+            tuning: Tuning::EqualTemperament,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    NaturalMinor,
+    HarmonicMinor,
+    MajorPentatonic,
+    MinorPentatonic,
+    /// Degrees given in cents above the tonic, each below 1200. Custom scales are tuned exactly
+    /// as written, so `Tuning` does not apply to them.
+    Custom(Vec<f32>),
+}
+
+impl Scale {
+    /// The semitones above the tonic that belong to one of the twelve-tone scales. Custom scales
+    /// have none.
+    fn semitones(&self) -> &'static [u8] {
+        match self {
+            Scale::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            Scale::Major => &[0, 2, 4, 5, 7, 9, 11],
+            Scale::NaturalMinor => &[0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            Scale::MajorPentatonic => &[0, 2, 4, 7, 9],
+            Scale::MinorPentatonic => &[0, 3, 5, 7, 10],
+            Scale::Custom(_) => &[],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tuning {
+    /// Twelve-tone equal temperament.
+    EqualTemperament,
+    /// Five-limit just intonation relative to the tonic of the key.
+    JustIntonation,
+}
+
+/// Five-limit just ratios for each semitone above the tonic.
+const JUST_RATIOS: [f32; 12] = [
+    1.0, 16.0 / 15.0, 9.0 / 8.0, 6.0 / 5.0, 5.0 / 4.0, 4.0 / 3.0,
+    45.0 / 32.0, 3.0 / 2.0, 8.0 / 5.0, 5.0 / 3.0, 9.0 / 5.0, 15.0 / 8.0,
+];
+
+/// Frequency in Hz of a MIDI note in equal temperament.
+pub fn midi_to_frequency(note: f32) -> f32 {
+    CONCERT_PITCH * 2f32.powf((note - 69.0) / 12.0)
+}
+
+/// The (fractional) MIDI note of a frequency in equal temperament.
+pub fn frequency_to_midi(frequency: f32) -> f32 {
+    69.0 + 12.0 * (frequency / CONCERT_PITCH).log2()
+}
+
+/// Turns frequency gene values into Hz for one `PitchMapping`. The list of playable pitches is
+/// worked out once, so a decoder can be reused for every note of a genome.
+pub struct PitchDecoder {
+    /// Allowed frequencies in ascending order, or `None` for the legacy mapping.
+    pitches: Option<Vec<f32>>,
+}
+
+impl PitchDecoder {
+    pub fn new(mapping: &PitchMapping) -> Self {
+        let pitches = match mapping {
+            PitchMapping::Legacy => None,
+            PitchMapping::Musical { lowest_note, highest_note, key, scale, tuning } => {
+                Some(scale_pitches(*lowest_note, *highest_note, *key, scale, *tuning))
+            }
+        };
+        PitchDecoder { pitches }
+    }
+
+    /// Maps a gene value in `0..=max_value` to a frequency.
+    pub fn frequency(&self, value: u32, max_value: u32) -> f32 {
+        let Some(pitches) = &self.pitches else {
+            return value as f32 * 5.0; // Frequency range from 0 to 1275 Hz
+        };
+        let index = (value as usize * pitches.len()) / (max_value as usize + 1);
+        pitches[index.min(pitches.len() - 1)]
+    }
+}
+
+fn scale_pitches(
+    lowest_note: u8, highest_note: u8, key: u8, scale: &Scale, tuning: Tuning
+) -> Vec<f32> {
+    let (low, high) = (lowest_note.min(highest_note), lowest_note.max(highest_note));
+    let key = (key % 12) as i32;
+
+    let mut pitches: Vec<f32> = match scale {
+        Scale::Custom(cents) => {
+            let low_hz = midi_to_frequency(low as f32);
+            let high_hz = midi_to_frequency(high as f32);
+            // Walk up an octave at a time from the tonic at or below the bottom of the range.
+            let first_tonic = low as i32 - (low as i32 - key).rem_euclid(12);
+            let mut tonic = midi_to_frequency(first_tonic as f32);
+            let mut pitches = Vec::new();
+            while tonic <= high_hz {
+                for &cent in cents {
+                    let pitch = tonic * 2f32.powf(cent.rem_euclid(1200.0) / 1200.0);
+                    // A small tolerance so the range ends are not lost to rounding.
+                    if pitch >= low_hz * 0.999 && pitch <= high_hz * 1.001 {
+                        pitches.push(pitch);
+                    }
+                }
+                tonic *= 2.0;
+            }
+            pitches
+        }
+        _ => {
+            let semitones = scale.semitones();
+            (low..=high)
+                .filter_map(|note| {
+                    let degree = (note as i32 - key).rem_euclid(12);
+                    if !semitones.contains(&(degree as u8)) {
+                        return None;
+                    }
+                    Some(match tuning {
+                        Tuning::EqualTemperament => midi_to_frequency(note as f32),
+                        Tuning::JustIntonation => {
+                            let tonic = note as i32 - degree;
+                            midi_to_frequency(tonic as f32) * JUST_RATIOS[degree as usize]
+                        }
+                    })
+                })
+                .collect()
+        }
+    };
+
+    pitches.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    pitches.dedup();
+    // A custom scale with no degrees in range still has to produce some note.
+    if pitches.is_empty() {
+        pitches.push(midi_to_frequency(low as f32));
+    }
+    pitches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn midi_notes_and_frequencies_convert_both_ways() {
+        assert_eq!(midi_to_frequency(69.0), CONCERT_PITCH);
+        assert!(close(midi_to_frequency(60.0), 261.626));
+        assert!(close(frequency_to_midi(880.0), 81.0));
+        // Fractions of a note are cents: 450 Hz is about 39 cents above A4.
+        assert!(close(frequency_to_midi(450.0), 69.389));
+    }
+
+    #[test]
+    fn legacy_values_count_five_hertz_steps() {
+        let decoder = PitchDecoder::new(&PitchMapping::Legacy);
+        assert_eq!(decoder.frequency(0, 255), 0.0);
+        assert_eq!(decoder.frequency(88, 255), 440.0);
+        assert_eq!(decoder.frequency(255, 255), 1275.0);
+    }
+
+    #[test]
+    fn values_spread_evenly_over_the_scale() {
+        // C major from C3 to C6 has 22 pitches, so each covers about 11.6 of the 256 values.
+        let decoder = PitchDecoder::new(&PitchMapping::default());
+        let note = |value| frequency_to_midi(decoder.frequency(value, 255)).round() as u8;
+        assert_eq!(note(0), 48);
+        assert_eq!(note(11), 48);
+        assert_eq!(note(12), 50);
+        assert_eq!(note(128), 67);
+        assert_eq!(note(255), 84);
+        let notes: Vec<u8> = (0..=255).map(note).collect();
+        assert!(notes.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(notes.iter().all(|note| [0, 2, 4, 5, 7, 9, 11].contains(&(note % 12))));
+    }
+
+    #[test]
+    fn tunings_place_degrees_in_cents() {
+        let tuned = |key, tuning| scale_pitches(60, 72, key, &Scale::Major, tuning);
+        let cents = |frequency: f32, note: f32| (frequency_to_midi(frequency) - note) * 100.0;
+
+        let equal = tuned(0, Tuning::EqualTemperament);
+        assert_eq!(equal.len(), 8);
+        assert!(equal.iter().zip([60, 62, 64, 65, 67, 69, 71, 72]).all(|(&f, n)| {
+            cents(f, n as f32).abs() < 0.1
+        }));
+        // A just major third is about 13.7 cents flat of equal temperament, a fifth 2 cents sharp.
+        let just = tuned(0, Tuning::JustIntonation);
+        assert!((cents(just[2], 64.0) + 13.69).abs() < 0.1, "{}", cents(just[2], 64.0));
+        assert!((cents(just[4], 67.0) - 1.96).abs() < 0.1, "{}", cents(just[4], 67.0));
+
+        // In G major the F is sharpened.
+        let g_major = tuned(7, Tuning::EqualTemperament);
+        assert!(g_major.iter().any(|&f| cents(f, 66.0).abs() < 0.1));
+        assert!(g_major.iter().all(|&f| cents(f, 65.0).abs() > 50.0));
+    }
+
+    #[test]
+    fn custom_scales_are_tuned_as_written() {
+        let neutral_third = Scale::Custom(vec![0.0, 350.0]);
+        let pitches = scale_pitches(60, 72, 0, &neutral_third, Tuning::JustIntonation);
+        assert_eq!(pitches.len(), 3);
+        assert!(close(frequency_to_midi(pitches[1]), 63.5));
+        assert!(close(pitches[2], 2.0 * pitches[0]));
+        // A scale with no degrees in range still plays the bottom note.
+        let tritone = Scale::Custom(vec![600.0]);
+        let empty = scale_pitches(60, 61, 0, &tritone, Tuning::EqualTemperament);
+        assert_eq!(empty, [midi_to_frequency(60.0)]);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::f32::consts::FRAC_PI_2;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use rodio::{OutputStream, Sink};
+
+use crate::decode_genome::DecodedGenome;
+use crate::graph::Node;
+use crate::render::RenderConfig;
+use crate::stream::{SongStream, BLOCK_FRAMES};
+
+/// How one song hands over to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transition {
+    /// Silence between songs; a zero gap plays them back to back.
+    Gap(Duration),
+    /// The next song fades in over the last part of the current one, with equal-power fades.
+    Crossfade(Duration),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaybackConfig {
+    pub render: RenderConfig,
+    pub transition: Transition,
+}
+
+impl Default for PlaybackConfig {
+    /// The default render, with songs played back to back.
+    fn default() -> Self {
+        PlaybackConfig {
+            render: RenderConfig::default(),
+            transition: Transition::Gap(Duration::ZERO),
+        }
+    }
+}
+
+/// What is playing, as reported to the position callback.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaybackPosition {
+    /// The id the song was queued under.
+    pub song_id: usize,
+    /// How far into the song playback is.
+    pub position: Duration,
+    pub duration: Duration,
+}
+
+type PositionCallback = Box<dyn FnMut(&PlaybackPosition) + Send>;
+
+/// Plays a queue of songs through the default audio device as one continuous stream, so a
+/// listening session over a whole population runs without stopping between songs.
+pub struct Player {
+    _stream: OutputStream,
+    sink: Sink,
+    playlist: Playlist,
+}
+
+impl Player {
+    pub fn new(config: PlaybackConfig) -> Result<Self, Box<dyn Error>> {
+        let (stream, stream_handle) = OutputStream::try_default()?;
+        let sink = Sink::try_new(&stream_handle)?;
+        let (playlist, source) = Playlist::new(config);
+        sink.append(source);
+        Ok(Player { _stream: stream, sink, playlist })
+    }
+
+    /// The queue feeding the player, for adding songs and moving around in them.
+    pub fn playlist(&self) -> &Playlist {
+        &self.playlist
+    }
+
+    pub fn pause(&self) {
+        self.sink.pause();
+    }
+
+    pub fn resume(&self) {
+        self.sink.play();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.sink.is_paused()
+    }
+}
+
+/// Controls a `PlaylistSource` from any thread. Songs are rendered as they play, so adding one
+/// is cheap.
+#[derive(Clone)]
+pub struct Playlist {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+}
+
+struct Shared {
+    queue: VecDeque<(usize, DecodedGenome)>,
+    skip: bool,
+    seek: Option<Duration>,
+    callback: Option<PositionCallback>,
+    /// Nothing is queued or playing.
+    idle: bool,
+}
+
+impl Playlist {
+    /// Creates an empty playlist and the source that plays it. The source never ends: when the
+    /// queue runs dry it plays silence until more songs arrive.
+    pub fn new(config: PlaybackConfig) -> (Playlist, PlaylistSource) {
+        let shared = Arc::new((
+            Mutex::new(Shared {
+                queue: VecDeque::new(),
+                skip: false,
+                seek: None,
+                callback: None,
+                idle: true,
+            }),
+            Condvar::new(),
+        ));
+        let source = PlaylistSource {
+            shared: Arc::clone(&shared),
+            config,
+            current: None,
+            incoming: None,
+            gap_remaining: 0,
+            block: Vec::new(),
+            cursor: 0,
+        };
+        (Playlist { shared }, source)
+    }
+
+    /// Adds a song to the end of the queue. `song_id` is only used to report what is playing.
+    pub fn enqueue(&self, song_id: usize, decoded: &DecodedGenome) {
+        let mut shared = self.shared.0.lock().unwrap();
+        shared.queue.push_back((song_id, decoded.clone()));
+        shared.idle = false;
+    }
+
+    /// Queues every song of a node, in the node's order.
+    pub fn enqueue_node(&self, node: &Node) {
+        for song in &node.songs {
+            self.enqueue(song.id, &song.decoded);
+        }
+    }
+
+    /// Removes every song that has not started yet.
+    pub fn clear(&self) {
+        self.shared.0.lock().unwrap().queue.clear();
+    }
+
+    /// Stops the current song and moves straight on to the next, without a gap or crossfade.
+    pub fn skip(&self) {
+        self.shared.0.lock().unwrap().skip = true;
+    }
+
+    /// Jumps to `position` within the current song.
+    pub fn seek(&self, position: Duration) {
+        self.shared.0.lock().unwrap().seek = Some(position);
+    }
+
+    /// Registers a callback told what is playing about every `BLOCK_FRAMES` frames. It runs on
+    /// the audio thread, so it should return quickly, but it may control the playlist.
+    pub fn on_position(&self, callback: impl FnMut(&PlaybackPosition) + Send + 'static) {
+        self.shared.0.lock().unwrap().callback = Some(Box::new(callback));
+    }
+
+    /// Blocks until every queued song has finished playing.
+    pub fn wait_until_idle(&self) {
+        let (lock, idle_changed) = &*self.shared;
+        let mut shared = lock.lock().unwrap();
+        while !shared.idle {
+            shared = idle_changed.wait(shared).unwrap();
+        }
+    }
+}
+
+/// A song playing from the queue.
+struct Playing {
+    song_id: usize,
+    decoded: DecodedGenome,
+    stream: SongStream,
+}
+
+/// The audio of a `Playlist`: every queued song in turn, with the configured transitions.
+pub struct PlaylistSource {
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    config: PlaybackConfig,
+    current: Option<Playing>,
+    /// The next song while it is crossfading in.
+    incoming: Option<Playing>,
+    /// Frames of silence still to play before the next song.
+    gap_remaining: usize,
+    block: Vec<f32>,
+    cursor: usize,
+}
+
+impl PlaylistSource {
+    fn channels(&self) -> usize {
+        self.config.render.channels.max(1) as usize
+    }
+
+    fn frames(&self, duration: Duration) -> usize {
+        (duration.as_secs_f64() * self.config.render.sample_rate as f64) as usize
+    }
+
+    fn start(&self, (song_id, decoded): (usize, DecodedGenome)) -> Playing {
+        let stream = SongStream::new(&decoded, &self.config.render);
+        Playing { song_id, decoded, stream }
+    }
+
+    /// Takes the next song off the queue. The lock is held only for the pop, never while audio
+    /// is rendered, so the `Playlist` stays responsive from other threads.
+    fn next_queued(&self) -> Option<Playing> {
+        let song = self.shared.0.lock().unwrap().queue.pop_front();
+        song.map(|song| self.start(song))
+    }
+
+    /// Carries out commands from the `Playlist` and renders the next block of frames.
+    fn fill_block(&mut self) {
+        let (skip, seek) = {
+            let mut shared = self.shared.0.lock().unwrap();
+            (std::mem::take(&mut shared.skip), shared.seek.take())
+        };
+        if skip {
+            self.current = self.incoming.take();
+            self.gap_remaining = 0;
+        }
+        if let Some(position) = seek {
+            if let Some(playing) = self.current.as_mut() {
+                if position < playing.stream.elapsed() {
+                    playing.stream = SongStream::new(&playing.decoded, &self.config.render);
+                }
+                playing.stream.skip_to(position);
+            }
+        }
+
+        let channels = self.channels();
+        self.block.clear();
+        self.cursor = 0;
+        for _ in 0..BLOCK_FRAMES {
+            self.next_frame();
+        }
+        debug_assert_eq!(self.block.len(), BLOCK_FRAMES * channels);
+
+        let shared = Arc::clone(&self.shared);
+        let (lock, idle_changed) = &*shared;
+        let mut shared = lock.lock().unwrap();
+        let idle = self.current.is_none() && self.gap_remaining == 0 && shared.queue.is_empty();
+        if idle && !shared.idle {
+            shared.idle = true;
+            idle_changed.notify_all();
+        }
+
+        // The callback is called without the lock held so that it can use the playlist.
+        let Some(playing) = &self.current else { return };
+        let Some(mut callback) = shared.callback.take() else { return };
+        drop(shared);
+        callback(&PlaybackPosition {
+            song_id: playing.song_id,
+            position: playing.stream.elapsed(),
+            duration: playing.stream.duration(),
+        });
+        lock.lock().unwrap().callback.get_or_insert(callback);
+    }
+
+    /// Appends one frame to the block, moving on through the queue as songs end.
+    fn next_frame(&mut self) {
+        let channels = self.channels();
+        if self.gap_remaining > 0 {
+            self.gap_remaining -= 1;
+            self.block.extend(std::iter::repeat_n(0.0, channels));
+            return;
+        }
+        if self.current.is_none() {
+            self.current = self.next_queued();
+        }
+        let Some(current) = self.current.as_ref() else {
+            self.block.extend(std::iter::repeat_n(0.0, channels));
+            return;
+        };
+
+        let remaining = current.stream.remaining_frames();
+        let crossfade = match self.config.transition {
+            Transition::Crossfade(length) => self.frames(length),
+            Transition::Gap(_) => 0,
+        };
+        if remaining == 0 {
+            // The song is over: hand over to whatever is crossfading in, or start the gap.
+            self.current = self.incoming.take();
+            if self.current.is_none() {
+                if let Transition::Gap(gap) = self.config.transition {
+                    self.gap_remaining = self.frames(gap);
+                }
+            }
+            return self.next_frame();
+        }
+        if remaining <= crossfade && self.incoming.is_none() {
+            self.incoming = self.next_queued();
+        }
+
+        let current = self.current.as_mut().unwrap();
+        match self.incoming.as_mut() {
+            Some(incoming) => {
+                let progress = 1.0 - remaining as f32 / crossfade as f32;
+                let fade_out = (progress * FRAC_PI_2).cos();
+                let fade_in = (progress * FRAC_PI_2).sin();
+                for _ in 0..channels {
+                    let outgoing = current.stream.next().unwrap_or(0.0);
+                    let incoming = incoming.stream.next().unwrap_or(0.0);
+                    self.block.push(outgoing * fade_out + incoming * fade_in);
+                }
+            }
+            None => self.block.extend(current.stream.by_ref().take(channels)),
+        }
+    }
+}
+
+impl Iterator for PlaylistSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.cursor == self.block.len() {
+            self.fill_block();
+        }
+        let sample = self.block[self.cursor];
+        self.cursor += 1;
+        Some(sample)
+    }
+}
+
+impl rodio::Source for PlaylistSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None // The format never changes mid-stream.
+    }
+
+    fn channels(&self) -> u16 {
+        self.config.render.channels.max(1)
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.config.render.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode_genome::{DecodedParameters, Timbre, WaveFunction};
+    use crate::envelope::Envelope;
+
+    const RATE: u32 = 8_000;
+
+    fn render() -> RenderConfig {
+        RenderConfig { sample_rate: RATE, channels: 2, ..RenderConfig::default() }
+    }
+
+    fn song(length_ms: u64) -> DecodedGenome {
+        DecodedGenome {
+            notes: vec![DecodedParameters {
+                start_time: Duration::ZERO,
+                frequency: 440.0,
+                amplitude: 0.5,
+                duration: Duration::from_millis(length_ms),
+                phase: 0.0,
+                wave_function: Some(WaveFunction::Sine),
+                envelope: Envelope::default(),
+                pan: 0.0,
+            }],
+            effects: Vec::new(),
+            timbre: Timbre::default(),
+            tempo: None,
+        }
+    }
+
+    /// Plays `songs` one after the other and counts the frames from the start of the first to
+    /// the end of the last.
+    fn frames_played(transition: Transition, songs: &[DecodedGenome]) -> usize {
+        let (playlist, mut source) = Playlist::new(PlaybackConfig { render: render(), transition });
+        for (id, song) in songs.iter().enumerate() {
+            playlist.enqueue(id, song);
+        }
+        let mut frames = 0;
+        loop {
+            source.next_frame();
+            let queued = !source.shared.0.lock().unwrap().queue.is_empty();
+            if source.current.is_none() && source.gap_remaining == 0 && !queued {
+                // The frame that found the last song over is silence.
+                return frames;
+            }
+            frames += 1;
+        }
+    }
+
+    fn song_frames(song: &DecodedGenome) -> usize {
+        SongStream::new(song, &render()).remaining_frames()
+    }
+
+    #[test]
+    fn crossfade_overlaps_the_songs_by_its_length() {
+        let (first, second) = (song(900), song(700));
+        let crossfade = Transition::Crossfade(Duration::from_millis(250));
+        let expected = song_frames(&first) + song_frames(&second) - RATE as usize / 4;
+        assert_eq!(frames_played(crossfade, &[first, second]), expected);
+    }
+}
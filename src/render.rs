@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::decode_genome::DecodedGenome;
+use crate::mastering::{master, MasteringConfig};
+use crate::stream::SongStream;
+
+/// Sample rate, in Hz, used when no other rate is asked for.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// Output format requested from `render`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderConfig {
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Stereo width of reverb tails, from 0.0 (mono) to 1.0. Ignored for mono output.
+    pub reverb_width: f32,
+    /// Mastering applied to the finished mix, or `None` for the raw sum of the notes.
+    pub mastering: Option<MasteringConfig>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig {
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            channels: 2,
+            reverb_width: 1.0,
+            mastering: Some(MasteringConfig::default()),
+        }
+    }
+}
+
+/// Rendered audio. Samples are interleaved frame by frame, so a stereo buffer holds
+/// `[left, right, left, right, ...]`.
+#[derive(Clone, Debug)]
+pub struct AudioBuffer {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioBuffer {
+    /// Number of frames, i.e. samples per channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    /// Iterates over the samples of a single channel.
+    pub fn channel(&self, channel: u16) -> impl Iterator<Item = f32> + '_ {
+        self.samples
+            .iter()
+            .skip(channel as usize)
+            .step_by(self.channels.max(1) as usize)
+            .copied()
+    }
+}
+
+/// Renders the decoded genome into an in-memory buffer without touching an audio device or
+/// printing anything. A single channel holds the plain sum of the notes; any other channel count
+/// gets a stereo mix, with each note placed by its pan and the pair repeated across any further
+/// channels. The mix is then mastered. See `SongStream` to render a block at a time instead.
+pub fn render(decoded: &DecodedGenome, config: &RenderConfig) -> AudioBuffer {
+    // Mastering is left to the end, since normalisation needs the whole mix.
+    let stream = SongStream::new(decoded, &RenderConfig { mastering: None, ..*config });
+    let mut buffer = AudioBuffer {
+        samples: stream.collect(),
+        sample_rate: config.sample_rate,
+        channels: config.channels.max(1),
+    };
+    if let Some(mastering) = &config.mastering {
+        master(&mut buffer, mastering);
+    }
+    buffer
+}
@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+/// How the start-time and duration genes of a note are turned into time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RhythmMapping {
+    /// The original mapping: both genes count 30 ms steps, up to 7.65 s.
+    #[default]
+    Legacy,
+    /// Notes start on a metrical grid and last a whole number of grid units.
+    Grid {
+        tempo: Tempo,
+        time_signature: TimeSignature,
+        /// Grid steps per beat, e.g. 4 for a sixteenth-note grid in 4/4.
+        subdivision: u8,
+        /// Length of the loop the start positions wrap around, in bars.
+        bars: u8,
+        /// Whether notes may also start on, and last for, triplet divisions of the beat.
+        triplets: bool,
+    },
+}
+
+impl RhythmMapping {
+    /// A four-bar loop in 4/4 on a sixteenth-note grid, with triplets and a tempo between 80 and
+    /// 160 BPM chosen by the genome.
+    pub fn grid() -> Self {
+        RhythmMapping::Grid {
+            tempo: Tempo::Genome { min_bpm: 80.0, max_bpm: 160.0 },
+            time_signature: TimeSignature { beats_per_bar: 4, beat_unit: 4 },
+            subdivision: 4,
+            bars: 4,
+            triplets: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tempo {
+    /// The same tempo, in beats per minute, for every genome.
+    Fixed(f32),
+    /// Read from the genome's tempo chromosome and scaled into this range.
+    Genome { min_bpm: f32, max_bpm: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats_per_bar: u8,
+    /// The note value that gets one beat: 4 for crotchets, 8 for quavers.
+    pub beat_unit: u8,
+}
+
+/// Tempo assumed for songs that were not decoded onto a rhythmic grid, wherever a tempo is needed
+/// anyway.
+pub const DEFAULT_BPM: f32 = 120.0;
+
+/// Note lengths, in beats, that the duration gene chooses between. The triplet lengths are left
+/// out when triplets are disabled.
+const NOTE_LENGTHS: [(f32, bool); 13] = [
+    (0.25, false), (1.0 / 3.0, true), (0.5, false), (2.0 / 3.0, true), (0.75, false),
+    (1.0, false), (4.0 / 3.0, true), (1.5, false), (2.0, false), (3.0, false), (4.0, false),
+    (6.0, false), (8.0, false),
+];
+
+/// Turns start-time and duration gene values into time for one `RhythmMapping`, with the tempo
+/// already resolved for the genome being decoded.
+pub struct RhythmDecoder {
+    grid: Option<ResolvedGrid>,
+}
+
+struct ResolvedGrid {
+    bpm: f32,
+    beat_seconds: f32,
+    subdivision: u32,
+    beats_per_loop: u32,
+    triplets: bool,
+    lengths: Vec<f32>,
+}
+
+impl RhythmDecoder {
+    /// `tempo_value` is the 8-bit value of the expressed tempo chromosome; it is only read for
+    /// `Tempo::Genome`.
+    pub fn new(mapping: &RhythmMapping, tempo_value: u32) -> Self {
+        let grid = match mapping {
+            RhythmMapping::Legacy => None,
+            RhythmMapping::Grid { tempo, time_signature, subdivision, bars, triplets } => {
+                let bpm = match *tempo {
+                    Tempo::Fixed(bpm) => bpm,
+                    Tempo::Genome { min_bpm, max_bpm } => {
+                        min_bpm + (max_bpm - min_bpm) * tempo_value as f32 / 255.0
+                    }
+                }.max(1.0);
+                // Tempo is counted in crotchets, so an 8 beat unit makes each beat half as long.
+                let beat_seconds = 60.0 / bpm * 4.0 / time_signature.beat_unit.max(1) as f32;
+                Some(ResolvedGrid {
+                    bpm,
+                    beat_seconds,
+                    subdivision: (*subdivision).max(1) as u32,
+                    beats_per_loop: (time_signature.beats_per_bar as u32 * *bars as u32).max(1),
+                    triplets: *triplets,
+                    lengths: NOTE_LENGTHS
+                        .iter()
+                        .filter(|(_, triplet)| *triplets || !triplet)
+                        .map(|(beats, _)| *beats)
+                        .collect(),
+                })
+            }
+        };
+        RhythmDecoder { grid }
+    }
+
+    /// The tempo notes are placed at, if they are on a grid.
+    pub fn bpm(&self) -> Option<f32> {
+        self.grid.as_ref().map(|grid| grid.bpm)
+    }
+
+    /// Maps an 8-bit start-time gene to a note onset. On a grid with triplets enabled the top bit
+    /// chooses between the straight and the triplet grid and the remaining bits give the step;
+    /// either way the step wraps around the length of the loop.
+    pub fn start_time(&self, value: u32) -> Duration {
+        let Some(grid) = &self.grid else {
+            return Duration::from_millis(value as u64 * 30);
+        };
+        let (steps_per_beat, step) = if grid.triplets && value >= 128 {
+            (3, value - 128)
+        } else {
+            (grid.subdivision, value)
+        };
+        let steps_per_loop = steps_per_beat * grid.beats_per_loop;
+        let beats = (step % steps_per_loop) as f32 / steps_per_beat as f32;
+        Duration::from_secs_f32(beats * grid.beat_seconds)
+    }
+
+    /// Maps an 8-bit duration gene to a note length, chosen from sixteenths up to two bars.
+    pub fn duration(&self, value: u32) -> Duration {
+        let Some(grid) = &self.grid else {
+            return Duration::from_millis(value as u64 * 30);
+        };
+        let index = (value as usize * grid.lengths.len()) / 256;
+        let beats = grid.lengths[index.min(grid.lengths.len() - 1)];
+        Duration::from_secs_f32(beats * grid.beat_seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One bar of 4/4 at 120 BPM on a sixteenth-note grid, so a beat lasts half a second.
+    fn one_bar(triplets: bool, beat_unit: u8) -> RhythmDecoder {
+        let mapping = RhythmMapping::Grid {
+            tempo: Tempo::Fixed(120.0),
+            time_signature: TimeSignature { beats_per_bar: 4, beat_unit },
+            subdivision: 4,
+            bars: 1,
+            triplets,
+        };
+        RhythmDecoder::new(&mapping, 0)
+    }
+
+    fn seconds(duration: Duration) -> f32 {
+        duration.as_secs_f32()
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn legacy_values_count_thirty_millisecond_steps() {
+        let decoder = RhythmDecoder::new(&RhythmMapping::Legacy, 0);
+        assert_eq!(decoder.bpm(), None);
+        assert_eq!(decoder.start_time(10), Duration::from_millis(300));
+        assert_eq!(decoder.duration(255), Duration::from_millis(7650));
+    }
+
+    #[test]
+    fn start_values_pick_grid_positions() {
+        let decoder = one_bar(true, 4);
+        assert_eq!(decoder.bpm(), Some(120.0));
+        assert!(close(seconds(decoder.start_time(0)), 0.0));
+        assert!(close(seconds(decoder.start_time(5)), 0.625));
+        // Sixteen sixteenths fill the bar, so the next step wraps back to the downbeat.
+        assert!(close(seconds(decoder.start_time(15)), 1.875));
+        assert!(close(seconds(decoder.start_time(16)), 0.0));
+        // The top bit switches to the triplet grid, twelve steps to the bar.
+        assert!(close(seconds(decoder.start_time(128 + 1)), 0.5 / 3.0));
+        assert!(close(seconds(decoder.start_time(128 + 13)), 0.5 / 3.0));
+
+        // Without triplets the top bit is just part of the step.
+        let straight = one_bar(false, 4);
+        assert!(close(seconds(straight.start_time(128 + 2)), 0.25));
+        // With quaver beats every position comes twice as soon.
+        let quavers = one_bar(true, 8);
+        assert!(close(seconds(quavers.start_time(5)), 0.3125));
+    }
+
+    #[test]
+    fn duration_values_pick_note_lengths() {
+        let decoder = one_bar(true, 4);
+        assert!(close(seconds(decoder.duration(0)), 0.125));
+        assert!(close(seconds(decoder.duration(20)), 0.5 / 3.0));
+        assert!(close(seconds(decoder.duration(255)), 4.0));
+        let straight = one_bar(false, 4);
+        assert!(close(seconds(straight.duration(26)), 0.25));
+        let lengths: Vec<f32> = (0..=255).map(|value| seconds(decoder.duration(value))).collect();
+        assert!(lengths.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn the_tempo_gene_scales_into_its_range() {
+        let mapping = RhythmMapping::grid();
+        assert_eq!(RhythmDecoder::new(&mapping, 0).bpm(), Some(80.0));
+        assert_eq!(RhythmDecoder::new(&mapping, 255).bpm(), Some(160.0));
+        assert_eq!(RhythmDecoder::new(&mapping, 51).bpm(), Some(96.0));
+    }
+}
@@ -0,0 +1,12 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Random number generator used wherever a run has to be reproducible. ChaCha8 is used rather
+/// than `StdRng` because its output is guaranteed to be stable across `rand` releases, so a seed
+/// recorded in a bug report or experiment log keeps producing the same genomes.
+pub type SeededRng = ChaCha8Rng;
+
+/// Creates a reproducible random number generator from a seed.
+pub fn seeded_rng(seed: u64) -> SeededRng {
+    SeededRng::seed_from_u64(seed)
+}
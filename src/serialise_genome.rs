@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use serde::{Deserialize, Serialize};
+
+use crate::bits::{BitSlice, BitVec};
+use crate::genome::{Chromosome, Genome};
+use crate::genome_schema::SCHEMA;
+
+/// Version of the on-disk genome layout. Bump this whenever chromosomes are added, removed or
+/// reinterpreted, and give new schema entries this version as their `since`. Loaded genomes
+/// keep their file's version, which the decoder uses to read reinterpreted genes the old way.
+///
+/// 1. The original chromosomes.
+/// 2. Saw and triangle codons.
+/// 3. The timbre chromosome.
+/// 4. The tempo chromosome, and envelope genes after each note (see `NoteLayout`). Version 4
+///    files saved before envelope genes existed are read with them.
+/// 5. The dominance chromosome.
+/// 6. Band-pass and notch codons, a frequency and a Q gene for every filter, room size, damping
+///    and mix genes for the reverb, and a third gene for the echo (see `EffectLayout`).
+/// 7. Distortion, chorus, flanger, bitcrusher and compressor codons.
+/// 8. A scope gene after every effect.
+pub const FORMAT_VERSION: u16 = 8;
+
+const MAGIC: &[u8; 4] = b"MEVG";
+const JSON_FORMAT_NAME: &str = "music_evo genome";
+
+#[derive(Serialize, Deserialize)]
+struct GenomeFile {
+    format: String,
+    version: u16,
+    chromosomes: Vec<ChromosomeEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChromosomeEntry {
+    name: String,
+    left: String,
+    right: String,
+}
+
+/// Encodes the genome into the compact binary format, under the genome's own format version.
+///
+/// The layout is the `MEVG` magic, a little-endian `u16` format version and a `u16` chromosome
+/// count. Each chromosome follows as a length-prefixed name and then its left and right strands,
+/// each stored as a `u32` bit count followed by the bits packed eight to a byte, most significant
+/// bit first.
+pub fn genome_to_bytes(genome: &Genome) -> Vec<u8> {
+    let chromosomes = named_chromosomes(genome);
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&genome.version().to_le_bytes());
+    bytes.extend_from_slice(&(chromosomes.len() as u16).to_le_bytes());
+
+    for (name, chromosome) in chromosomes {
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        write_packed_strand(&mut bytes, chromosome.get_left_chromosome());
+        write_packed_strand(&mut bytes, chromosome.get_right_chromosome());
+    }
+
+    bytes
+}
+
+/// Decodes a genome from the binary format written by `genome_to_bytes`.
+pub fn genome_from_bytes(bytes: &[u8]) -> Result<Genome, Box<dyn Error>> {
+    let mut reader = ByteReader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("not a music_evo genome file".into());
+    }
+    let version = u16::from_le_bytes(reader.take(2)?.try_into()?);
+    check_version(version)?;
+
+    let count = u16::from_le_bytes(reader.take(2)?.try_into()?);
+    let mut chromosomes = HashMap::new();
+    for _ in 0..count {
+        let name_len = reader.take(1)?[0] as usize;
+        let name = String::from_utf8(reader.take(name_len)?.to_vec())?;
+        let left = read_packed_strand(&mut reader)?;
+        let right = read_packed_strand(&mut reader)?;
+        chromosomes.insert(name, Chromosome::new(left, right));
+    }
+
+    genome_from_chromosomes(chromosomes, version)
+}
+
+/// Encodes the genome as human-readable JSON, with each strand written as a string of `0`s and
+/// `1`s.
+pub fn genome_to_json(genome: &Genome) -> Result<String, Box<dyn Error>> {
+    let file = GenomeFile {
+        format: JSON_FORMAT_NAME.to_string(),
+        version: genome.version(),
+        chromosomes: named_chromosomes(genome)
+            .into_iter()
+            .map(|(name, chromosome)| ChromosomeEntry {
+                name: name.to_string(),
+                left: chromosome.get_left_chromosome().to_string(),
+                right: chromosome.get_right_chromosome().to_string(),
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&file)?)
+}
+
+/// Decodes a genome from the JSON written by `genome_to_json`.
+pub fn genome_from_json(json: &str) -> Result<Genome, Box<dyn Error>> {
+    let file: GenomeFile = serde_json::from_str(json)?;
+    if file.format != JSON_FORMAT_NAME {
+        return Err(format!("unexpected genome format '{}'", file.format).into());
+    }
+    check_version(file.version)?;
+
+    let mut chromosomes = HashMap::new();
+    for entry in file.chromosomes {
+        let left = strand_from_string(&entry.left)?;
+        let right = strand_from_string(&entry.right)?;
+        chromosomes.insert(entry.name, Chromosome::new(left, right));
+    }
+
+    genome_from_chromosomes(chromosomes, file.version)
+}
+
+/// Writes the genome to the specified filename in the binary format.
+pub fn save_genome(genome: &Genome, filename: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(filename, genome_to_bytes(genome))?;
+    Ok(())
+}
+
+/// Reads a genome from a file written by `save_genome`.
+pub fn load_genome(filename: &str) -> Result<Genome, Box<dyn Error>> {
+    genome_from_bytes(&fs::read(filename)?)
+}
+
+/// Writes the genome to the specified filename as JSON.
+pub fn save_genome_json(genome: &Genome, filename: &str) -> Result<(), Box<dyn Error>> {
+    fs::write(filename, genome_to_json(genome)?)?;
+    Ok(())
+}
+
+/// Reads a genome from a JSON file written by `save_genome_json`.
+pub fn load_genome_json(filename: &str) -> Result<Genome, Box<dyn Error>> {
+    genome_from_json(&fs::read_to_string(filename)?)
+}
+
+fn named_chromosomes(genome: &Genome) -> Vec<(&'static str, &Chromosome)> {
+    SCHEMA.chromosomes.iter().map(|spec| spec.name).zip(genome.chromosomes()).collect()
+}
+
+/// Assembles a genome from the chromosomes read out of a file written with format `version`.
+/// Chromosomes the file predates are left empty; see `ChromosomeSpec::since`.
+fn genome_from_chromosomes(
+    mut chromosomes: HashMap<String, Chromosome>, version: u16
+) -> Result<Genome, Box<dyn Error>> {
+    let chromosomes = SCHEMA
+        .chromosomes
+        .iter()
+        .map(|spec| match chromosomes.remove(spec.name) {
+            Some(chromosome) => Ok(chromosome),
+            None if version < spec.since => Ok(Chromosome::new(BitVec::new(), BitVec::new())),
+            None => Err(format!("genome file is missing the '{}' chromosome", spec.name).into()),
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    Ok(Genome::with_version(chromosomes, version))
+}
+
+fn check_version(version: u16) -> Result<(), Box<dyn Error>> {
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(format!(
+            "unsupported genome format version {} (this build reads up to {})",
+            version, FORMAT_VERSION
+        ).into());
+    }
+    Ok(())
+}
+
+fn write_packed_strand(bytes: &mut Vec<u8>, strand: BitSlice) {
+    bytes.extend_from_slice(&(strand.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&strand.to_bytes());
+}
+
+fn read_packed_strand(reader: &mut ByteReader) -> Result<BitVec, Box<dyn Error>> {
+    let bit_len = u32::from_le_bytes(reader.take(4)?.try_into()?) as usize;
+    let packed = reader.take(bit_len.div_ceil(8))?;
+    Ok(BitVec::from_bytes(packed, bit_len))
+}
+
+fn strand_from_string(strand: &str) -> Result<BitVec, Box<dyn Error>> {
+    strand
+        .chars()
+        .map(|c| match c {
+            '0' => Ok(0),
+            '1' => Ok(1),
+            other => Err(format!("invalid bit '{}' in chromosome strand", other).into()),
+        })
+        .collect()
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        let end = self.position + count;
+        if end > self.bytes.len() {
+            return Err("genome file ended unexpectedly".into());
+        }
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genome_schema::NOTES;
+    use crate::rng::seeded_rng;
+
+    fn random_genome() -> Genome {
+        Genome::initialise_random_genome_with_rng(100, 300, 4, 8, &mut seeded_rng(7))
+    }
+
+    fn assert_same_genome(a: &Genome, b: &Genome) {
+        for ((name, x), (_, y)) in named_chromosomes(a).into_iter().zip(named_chromosomes(b)) {
+            assert_eq!(x.get_left_chromosome(), y.get_left_chromosome(), "{} left", name);
+            assert_eq!(x.get_right_chromosome(), y.get_right_chromosome(), "{} right", name);
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let genome = random_genome();
+        assert_same_genome(&genome, &genome_from_bytes(&genome_to_bytes(&genome)).unwrap());
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let genome = random_genome();
+        let json = genome_to_json(&genome).unwrap();
+        assert_same_genome(&genome, &genome_from_json(&json).unwrap());
+    }
+
+    /// A genome saved by the first version of the format, before most of today's chromosomes
+    /// existed.
+    #[test]
+    fn loads_version_1_file() {
+        let genome = genome_from_bytes(include_bytes!("../tests/fixtures/genome_v1.mevg")).unwrap();
+        assert_eq!(genome.version(), 1);
+        assert_eq!(genome[NOTES].get_left_chromosome().len(), 122);
+        for (spec, chromosome) in SCHEMA.chromosomes.iter().zip(genome.chromosomes()) {
+            let empty = chromosome.get_left_chromosome().is_empty()
+                && chromosome.get_right_chromosome().is_empty();
+            assert_eq!(empty, spec.since > 1, "{}", spec.name);
+        }
+    }
+
+    #[test]
+    fn resaving_keeps_the_file_version() {
+        let genome = genome_from_bytes(include_bytes!("../tests/fixtures/genome_v1.mevg")).unwrap();
+        let resaved = genome_from_bytes(&genome_to_bytes(&genome)).unwrap();
+        assert_eq!(resaved.version(), 1);
+        assert_same_genome(&genome, &resaved);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = genome_to_bytes(&random_genome());
+        bytes[0] = b'X';
+        assert!(genome_from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let bytes = genome_to_bytes(&random_genome());
+        for length in [0, 3, 6, 8, bytes.len() / 2, bytes.len() - 1] {
+            assert!(genome_from_bytes(&bytes[..length]).is_err(), "{} bytes", length);
+        }
+    }
+
+    #[test]
+    fn rejects_future_version() {
+        let mut bytes = genome_to_bytes(&random_genome());
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(genome_from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,449 @@
+use std::f32::consts::{PI, SQRT_2};
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use crate::decode_genome::{
+    DecodedGenome, DecodedParameters, EffectScope, Timbre, WaveFunction,
+};
+use crate::effects::{effect_processor, EffectProcessor};
+use crate::mastering::soft_limit;
+use crate::oscillator::oscillate;
+use crate::render::RenderConfig;
+
+/// Frames synthesised at a time.
+pub const BLOCK_FRAMES: usize = 1024;
+
+/// How long an effect scoped to a section takes to fade in and out.
+const SECTION_FADE: f32 = 0.01;
+
+/// A song rendered on demand, one block of `BLOCK_FRAMES` at a time, so that playback can start
+/// straight away and long songs never have to be held in memory whole.
+///
+/// Iterating yields interleaved samples in the layout `render` produces, and the stream is also a
+/// `rodio::Source`. Normalisation has to hear the whole song first, so a stream applies only the
+/// limiter of its mastering settings; use `render` where songs must match in loudness.
+///
+/// Notes of a wave function that has effects scoped to it are mixed on a bus of their own, through
+/// those effects, before they join the master bus. The global and section effects then run on the
+/// master bus in the order they were decoded.
+pub struct SongStream {
+    sample_rate: u32,
+    channels: u16,
+    timbre: Timbre,
+    /// Notes yet to start, latest first so the next one can be popped off the end.
+    pending: Vec<Voice>,
+    active: Vec<Voice>,
+    buses: Vec<Bus>,
+    master: Vec<MasterEffect>,
+    limiter_ceiling: Option<f32>,
+    /// The master bus: one plane per synthesised channel, one for mono, left and right for stereo.
+    planes: Vec<Vec<f32>>,
+    /// The master bus before a section effect, to fade between.
+    dry: Vec<Vec<f32>>,
+    /// Length of the song in frames.
+    length: usize,
+    /// Frames rendered so far.
+    position: usize,
+    /// The current block, interleaved, and how much of it has been handed out.
+    block: Vec<f32>,
+    cursor: usize,
+}
+
+impl SongStream {
+    pub fn new(decoded: &DecodedGenome, config: &RenderConfig) -> Self {
+        let sample_rate = config.sample_rate;
+        let channels = config.channels.max(1);
+        let plane_count = if channels == 1 { 1 } else { 2 };
+
+        // Calculate the total length considering the start times and durations of all notes
+        let total_length = decoded
+            .notes
+            .iter()
+            .map(|n| n.end_time())
+            .max()
+            .unwrap_or_default();
+        let length = (total_length.as_secs_f32() * sample_rate as f32) as usize;
+
+        let frame =
+            |time: Duration| ((time.as_secs_f32() * sample_rate as f32) as usize).min(length);
+        let mut buses: Vec<Bus> = Vec::new();
+        let mut master = Vec::new();
+        for scoped in &decoded.effects {
+            let processor =
+                effect_processor(&scoped.effect, sample_rate, plane_count, config.reverb_width);
+            match scoped.scope {
+                EffectScope::Global => master.push(MasterEffect { processor, section: None }),
+                EffectScope::Section(start, end) => master.push(MasterEffect {
+                    processor,
+                    section: Some((frame(start), frame(end))),
+                }),
+                EffectScope::Voice(voice) => {
+                    match buses.iter_mut().find(|bus| bus.voice == voice) {
+                        Some(bus) => bus.effects.push(processor),
+                        None => buses.push(Bus {
+                            voice,
+                            effects: vec![processor],
+                            planes: vec![Vec::with_capacity(BLOCK_FRAMES); plane_count],
+                        }),
+                    }
+                }
+            }
+        }
+
+        let mut pending: Vec<Voice> = decoded
+            .notes
+            .iter()
+            .map(|note| {
+                let mut voice = Voice::new(note, sample_rate, length, plane_count == 2);
+                voice.bus = buses.iter().position(|bus| bus.voice == voice.wave_function);
+                voice
+            })
+            .collect();
+        pending.sort_by_key(|voice| std::cmp::Reverse(voice.start));
+
+        SongStream {
+            sample_rate,
+            channels,
+            timbre: decoded.timbre.clone(),
+            pending,
+            active: Vec::new(),
+            buses,
+            master,
+            limiter_ceiling: config.mastering.and_then(|mastering| mastering.limiter_ceiling),
+            planes: vec![Vec::with_capacity(BLOCK_FRAMES); plane_count],
+            dry: vec![Vec::with_capacity(BLOCK_FRAMES); plane_count],
+            length,
+            position: 0,
+            block: Vec::with_capacity(BLOCK_FRAMES * channels as usize),
+            cursor: 0,
+        }
+    }
+
+    /// Length of the whole song.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.length as f64 / self.sample_rate as f64)
+    }
+
+    /// How far into the song the next sample is.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_secs_f64(self.next_frame() as f64 / self.sample_rate as f64)
+    }
+
+    /// Frames left before the song ends.
+    pub fn remaining_frames(&self) -> usize {
+        self.length - self.next_frame()
+    }
+
+    /// Moves forward to `position` by rendering and discarding everything before it, so effects
+    /// arrive there in the state they would have reached playing through. A stream cannot move
+    /// backwards; build a new one and move that forward instead.
+    pub fn skip_to(&mut self, position: Duration) {
+        // Rounded to the nearest frame: a time worked out from a frame count, as `elapsed` is, can
+        // come back a hair short of that frame.
+        let target =
+            ((position.as_secs_f64() * self.sample_rate as f64).round() as usize).min(self.length);
+        let channels = self.channels as usize;
+        while target >= self.position && self.render_block() {}
+        if target >= self.position {
+            self.cursor = self.block.len();
+            return;
+        }
+        let block_start = self.position - self.block.len() / channels;
+        self.cursor = self.cursor.max(target.saturating_sub(block_start) * channels);
+    }
+
+    fn next_frame(&self) -> usize {
+        let channels = self.channels as usize;
+        self.position - (self.block.len() - self.cursor) / channels
+    }
+
+    /// Renders the next block into `self.block`. Returns false once the song is over.
+    fn render_block(&mut self) -> bool {
+        let block_start = self.position;
+        let frames = BLOCK_FRAMES.min(self.length - block_start);
+        if frames == 0 {
+            return false;
+        }
+        let block_end = block_start + frames;
+
+        let bus_planes = self.buses.iter_mut().flat_map(|bus| bus.planes.iter_mut());
+        for plane in self.planes.iter_mut().chain(bus_planes) {
+            plane.clear();
+            plane.resize(frames, 0.0);
+        }
+        while self.pending.last().is_some_and(|voice| voice.start < block_end) {
+            self.active.extend(self.pending.pop());
+        }
+        for voice in self.active.iter_mut() {
+            let planes = match voice.bus {
+                Some(bus) => &mut self.buses[bus].planes,
+                None => &mut self.planes,
+            };
+            voice.render(planes, block_start, self.sample_rate, &self.timbre);
+        }
+        self.active.retain(|voice| voice.end > block_end);
+
+        for bus in self.buses.iter_mut() {
+            let mut planes: Vec<&mut [f32]> =
+                bus.planes.iter_mut().map(|plane| plane.as_mut_slice()).collect();
+            for effect in bus.effects.iter_mut() {
+                effect.process(&mut planes);
+            }
+            for (master, plane) in self.planes.iter_mut().zip(&bus.planes) {
+                for (sample, bus_sample) in master.iter_mut().zip(plane) {
+                    *sample += bus_sample;
+                }
+            }
+        }
+
+        let fade = (SECTION_FADE * self.sample_rate as f32) as usize;
+        for effect in self.master.iter_mut() {
+            match effect.section {
+                None => {
+                    let mut planes: Vec<&mut [f32]> =
+                        self.planes.iter_mut().map(|plane| plane.as_mut_slice()).collect();
+                    effect.processor.process(&mut planes);
+                }
+                Some((start, end)) if start < block_end && block_start < end => {
+                    self.dry.clone_from(&self.planes);
+                    let mut planes: Vec<&mut [f32]> =
+                        self.planes.iter_mut().map(|plane| plane.as_mut_slice()).collect();
+                    effect.processor.process(&mut planes);
+
+                    let fade = fade.min((end - start) / 2).max(1);
+                    for (plane, dry) in self.planes.iter_mut().zip(&self.dry) {
+                        for (offset, (sample, dry)) in plane.iter_mut().zip(dry).enumerate() {
+                            let frame = block_start + offset;
+                            let gain = if frame < start || frame >= end {
+                                0.0
+                            } else {
+                                ((frame - start).min(end - 1 - frame) as f32 / fade as f32).min(1.0)
+                            };
+                            *sample = dry + (*sample - dry) * gain;
+                        }
+                    }
+                }
+                // Outside its section the effect is idle.
+                Some(_) => {}
+            }
+        }
+
+        let channels = self.channels as usize;
+        self.block.clear();
+        for frame in 0..frames {
+            for channel in 0..channels {
+                self.block.push(self.planes[channel % self.planes.len()][frame]);
+            }
+        }
+        if let Some(ceiling) = self.limiter_ceiling {
+            for sample in self.block.iter_mut() {
+                *sample = soft_limit(*sample, ceiling);
+            }
+        }
+        self.cursor = 0;
+        self.position = block_end;
+        true
+    }
+}
+
+impl Iterator for SongStream {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.cursor == self.block.len() && !self.render_block() {
+            return None;
+        }
+        let sample = self.block[self.cursor];
+        self.cursor += 1;
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.length - self.position) * self.channels as usize
+            + (self.block.len() - self.cursor);
+        (remaining, Some(remaining))
+    }
+}
+
+impl rodio::Source for SongStream {
+    fn current_frame_len(&self) -> Option<usize> {
+        None // The format never changes mid-stream.
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.duration())
+    }
+}
+
+/// The notes of one wave function, mixed apart from the rest through their own effects.
+struct Bus {
+    voice: WaveFunction,
+    effects: Vec<Box<dyn EffectProcessor>>,
+    planes: Vec<Vec<f32>>,
+}
+
+/// An effect on the master bus, and for a section effect the frames it is heard between.
+struct MasterEffect {
+    processor: Box<dyn EffectProcessor>,
+    section: Option<(usize, usize)>,
+}
+
+/// A note being synthesised, with its oscillator state carried from block to block.
+struct Voice {
+    note: DecodedParameters,
+    /// The bus the note is mixed on, or `None` for the master bus.
+    bus: Option<usize>,
+    /// First and one-past-last frame of the note, release included.
+    start: usize,
+    end: usize,
+    held: f32,
+    pan_gains: [f32; 2],
+    wave_function: WaveFunction,
+    phase: f64,
+    phase_increment: f64,
+}
+
+impl Voice {
+    /// Notes without a wave function are rendered as sines. In stereo the note is panned with a
+    /// constant-power law, scaled so a centred note is at full level on both sides.
+    fn new(note: &DecodedParameters, sample_rate: u32, length: usize, stereo: bool) -> Self {
+        let start = ((note.start_time.as_secs_f32() * sample_rate as f32) as usize).min(length);
+        let end = (start
+            + ((note.end_time() - note.start_time).as_secs_f32() * sample_rate as f32) as usize)
+            .min(length);
+        let pan_gains = if stereo {
+            let angle = (note.pan.clamp(-1.0, 1.0) + 1.0) * PI / 4.0;
+            [angle.cos() * SQRT_2, angle.sin() * SQRT_2]
+        } else {
+            [1.0, 1.0]
+        };
+        Voice {
+            note: note.clone(),
+            bus: None,
+            start,
+            end,
+            held: note.duration.as_secs_f32(),
+            pan_gains,
+            wave_function: note.wave_function.unwrap_or(WaveFunction::Sine),
+            // The phase is accumulated in cycles and in f64 so long notes do not drift out of
+            // tune.
+            phase: (note.phase as f64 / TAU).rem_euclid(1.0),
+            phase_increment: note.frequency as f64 / sample_rate as f64,
+        }
+    }
+
+    /// Adds the part of the note that falls within the block starting at frame `block_start`,
+    /// shaped by its envelope.
+    fn render(
+        &mut self, planes: &mut [Vec<f32>], block_start: usize, sample_rate: u32, timbre: &Timbre
+    ) {
+        let block_end = block_start + planes[0].len();
+        for frame in self.start.max(block_start)..self.end.min(block_end) {
+            let time = (frame - self.start) as f32 / sample_rate as f32;
+            let gain = self.note.envelope.gain(time, self.held);
+            let value = self.note.amplitude * gain * oscillate(
+                self.wave_function, timbre, self.phase as f32, self.phase_increment as f32
+            );
+            for (plane, pan_gain) in planes.iter_mut().zip(self.pan_gains) {
+                plane[frame - block_start] += value * pan_gain;
+            }
+            self.phase = (self.phase + self.phase_increment).fract();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode_genome::{Effect, ScopedEffect};
+    use crate::envelope::Envelope;
+    use crate::render::render;
+
+    const RATE: u32 = 8_000;
+
+    /// A few overlapping notes on two voices, with an effect of every scope, so that oscillator
+    /// and effect state both carry across blocks.
+    fn song() -> DecodedGenome {
+        let note = |wave_function, start_ms, length_ms, frequency, pan| DecodedParameters {
+            start_time: Duration::from_millis(start_ms),
+            frequency,
+            amplitude: 0.3,
+            duration: Duration::from_millis(length_ms),
+            phase: 0.0,
+            wave_function: Some(wave_function),
+            envelope: Envelope::default(),
+            pan,
+        };
+        let scoped = |effect, scope| ScopedEffect { effect, scope };
+        DecodedGenome {
+            notes: vec![
+                note(WaveFunction::Sine, 0, 400, 220.0, -0.5),
+                note(WaveFunction::Square, 130, 300, 330.0, 0.5),
+                note(WaveFunction::Sine, 350, 500, 440.0, 0.0),
+            ],
+            effects: vec![
+                scoped(Effect::LowPass(1500.0, 0.7), EffectScope::Voice(WaveFunction::Square)),
+                scoped(
+                    Effect::Distortion(5.0, 0.5),
+                    EffectScope::Section(Duration::from_millis(200), Duration::from_millis(600)),
+                ),
+                scoped(
+                    Effect::Echo(Duration::from_millis(90), 0.5, 4000.0, 2),
+                    EffectScope::Global,
+                ),
+            ],
+            timbre: Timbre::default(),
+            tempo: None,
+        }
+    }
+
+    fn config(channels: u16) -> RenderConfig {
+        RenderConfig { sample_rate: RATE, channels, mastering: None, ..RenderConfig::default() }
+    }
+
+    #[test]
+    fn blocks_join_up_into_the_full_render() {
+        for channels in [1, 2] {
+            let mut stream = SongStream::new(&song(), &config(channels));
+            let mut joined = Vec::new();
+            while stream.render_block() {
+                let frames = stream.block.len() / channels as usize;
+                assert!(frames == BLOCK_FRAMES || stream.position == stream.length);
+                joined.extend_from_slice(&stream.block);
+            }
+            let rendered = render(&song(), &config(channels));
+            assert!(rendered.frames() > 4 * BLOCK_FRAMES);
+            assert_eq!(joined, rendered.samples);
+        }
+    }
+
+    #[test]
+    fn skipping_matches_the_tail_of_the_full_render() {
+        let channels = 2;
+        let full = render(&song(), &config(channels as u16)).samples;
+        let length = full.len() / channels;
+        let block = BLOCK_FRAMES;
+        for frame in [0, 1, block - 1, block, 3 * block + 17, length, length + 5] {
+            let position = Duration::from_micros(frame as u64 * 1_000_000 / RATE as u64);
+            let mut stream = SongStream::new(&song(), &config(channels as u16));
+            stream.skip_to(position);
+            assert_eq!(stream.elapsed(), position.min(stream.duration()), "frame {}", frame);
+            let tail: Vec<f32> = stream.collect();
+            assert_eq!(tail, full[frame.min(length) * channels..], "frame {}", frame);
+        }
+
+        // Skipping from partway through a block lands in the same place.
+        let mut stream = SongStream::new(&song(), &config(channels as u16));
+        stream.by_ref().take(300 * channels).for_each(drop);
+        stream.skip_to(Duration::from_millis(400));
+        assert_eq!(stream.collect::<Vec<_>>(), full[3200 * channels..]);
+    }
+}
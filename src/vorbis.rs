@@ -0,0 +1,817 @@
+use std::error::Error;
+use std::f64::consts::PI;
+
+use crate::ogg::OggWriter;
+
+const VENDOR: &str = "music_evo";
+
+/// Every block is a long block of 2048 samples, so a single mode and window shape cover the
+/// whole stream. Transients smear a little more than with switched block sizes.
+const BLOCK_EXPONENT: u32 = 11;
+const BLOCK: usize = 1 << BLOCK_EXPONENT;
+/// Spectral lines per block, and samples each block adds to the output.
+const HALF: usize = BLOCK / 2;
+
+/// Floor 1 amplitudes step through every other entry of the decoder's dB table.
+const FLOOR_MULTIPLIER: usize = 2;
+const FLOOR_RANGE: i32 = 128;
+const FLOOR_RANGE_BITS: u32 = 10;
+const FLOOR_PARTITIONS: usize = 16;
+const FLOOR_CLASS_DIMENSIONS: usize = 2;
+/// Ratio between neighbouring entries of the floor 1 dB table, whose last entry is 1.0.
+const FLOOR_DB_STEP: f64 = 1.0649863;
+/// Floor points whose amplitude is within this many steps of the line through their neighbours
+/// are left on the line.
+const FLOOR_TOLERANCE: i32 = 1;
+
+/// Residue partitions are classified as silent, small (every value within ±2) or large.
+const PARTITION_SIZE: usize = 16;
+const CLASSIFICATIONS: usize = 3;
+/// Large values are coded as a coarse multiple of `COARSE_STEP` plus a fine correction.
+const COARSE_STEP: i32 = 15;
+const MAX_RESIDUE: i32 = 7 * COARSE_STEP + 7;
+
+const FLOOR_BOOK: usize = 0;
+const CLASS_BOOK: usize = 1;
+const SMALL_BOOK: usize = 2;
+const COARSE_BOOK: usize = 3;
+const FINE_BOOK: usize = 4;
+
+/// Encodes interleaved samples in [-1.0, 1.0] as Ogg Vorbis. `quality` runs from 0, the
+/// smallest files, to 10, the most faithful.
+///
+/// The encoder is deliberately simple: fixed long blocks, a floor that follows the spectral
+/// envelope, and residue quantised to a step a fixed ratio below it, with no psychoacoustic
+/// model and no channel coupling. Files are several times smaller than FLAC, though larger than
+/// a tuned encoder would make at the same quality.
+pub fn encode_ogg_vorbis(
+    samples: &[f32], channels: u16, sample_rate: u32, quality: u8
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    if channels == 0 || channels > 255 {
+        return Err(format!("Vorbis cannot store {} channels", channels).into());
+    }
+    if sample_rate == 0 {
+        return Err("sample rate must be non-zero".into());
+    }
+    if quality > 10 {
+        return Err(format!("Vorbis quality {} is above 10", quality).into());
+    }
+    let channels = channels as usize;
+    let encoder = Encoder::new(quality);
+
+    let mut ogg = OggWriter::new();
+    ogg.write_packet(&identification_header(channels, sample_rate), 0, false);
+    ogg.flush_page(false);
+    ogg.write_packet(&comment_header(), 0, false);
+    ogg.write_packet(&encoder.setup_header(), 0, false);
+    ogg.flush_page(false);
+
+    // Block k covers samples (k - 1) * HALF up to (k + 1) * HALF, and completes the output up
+    // to k * HALF, so the first block only primes the overlap.
+    let frames = samples.len() / channels;
+    let blocks = frames.div_ceil(HALF) + 1;
+    let mut block = vec![vec![0.0; BLOCK]; channels];
+    for k in 0..blocks {
+        for (channel, block) in block.iter_mut().enumerate() {
+            for (i, value) in block.iter_mut().enumerate() {
+                *value = (k * HALF + i)
+                    .checked_sub(HALF)
+                    .filter(|&frame| frame < frames)
+                    .map_or(0.0, |frame| samples[frame * channels + channel] as f64);
+            }
+        }
+        let last = k + 1 == blocks;
+        let granule = if last { frames } else { k * HALF };
+        if last {
+            // A page that both starts the audio and ends the stream is ambiguous about which end
+            // its short granule position trims, so the last packet always gets a page of its own.
+            ogg.flush_page(false);
+        }
+        ogg.write_packet(&encoder.audio_packet(&block), granule as u64, last);
+    }
+    Ok(ogg.bytes)
+}
+
+fn identification_header(channels: usize, sample_rate: u32) -> Vec<u8> {
+    let mut header = vec![1];
+    header.extend_from_slice(b"vorbis");
+    header.extend_from_slice(&0u32.to_le_bytes()); // Vorbis version
+    header.push(channels as u8);
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&[0; 12]); // No maximum, nominal or minimum bitrate
+    header.push((BLOCK_EXPONENT | BLOCK_EXPONENT << 4) as u8);
+    header.push(1); // Framing bit
+    header
+}
+
+fn comment_header() -> Vec<u8> {
+    let mut header = vec![3];
+    header.extend_from_slice(b"vorbis");
+    header.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    header.extend_from_slice(VENDOR.as_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes()); // No user comments
+    header.push(1); // Framing bit
+    header
+}
+
+struct Encoder {
+    books: Vec<Codebook>,
+    /// Floor 1 X positions in the order they are coded; the first two are the ends.
+    floor_x: Vec<usize>,
+    window: Vec<f64>,
+    mdct: Mdct,
+    /// The quantisation step is the local spectral peak divided by this.
+    peak_steps: f64,
+    /// The smallest quantisation step, relative to the spectrum of a full-scale sine.
+    min_step: f64,
+}
+
+impl Encoder {
+    fn new(quality: u8) -> Self {
+        let quality = quality as f64;
+        let floor_weights: Vec<f64> = (0..FLOOR_RANGE).map(|v| 1.0 / (1.0 + v as f64)).collect();
+        let books = vec![
+            // Floor amplitudes, which are mostly small corrections to the predicted line.
+            Codebook::new(1, &floor_weights, None),
+            // Pairs of partition classes.
+            Codebook::new(2, &[8.0, 4.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0, 1.0], None),
+            Codebook::grid(-2, 1, 5, 1.0),
+            Codebook::grid(-7 * COARSE_STEP, COARSE_STEP, 15, 1.5),
+            Codebook::grid(-7, 1, 15, 0.2),
+        ];
+        Encoder {
+            books,
+            floor_x: floor_points(),
+            window: (0..BLOCK)
+                .map(|i| (PI / 2.0 * (PI * (i as f64 + 0.5) / BLOCK as f64).sin().powi(2)).sin())
+                .collect(),
+            mdct: Mdct::new(HALF),
+            peak_steps: 4.0 * 2f64.powf(quality / 2.5),
+            min_step: 10f64.powf(-(66.0 + 3.0 * quality) / 20.0),
+        }
+    }
+
+    fn setup_header(&self) -> Vec<u8> {
+        let mut header = BitPacker::new();
+        header.write(5, 8);
+        for &byte in b"vorbis" {
+            header.write(byte as u32, 8);
+        }
+
+        header.write(self.books.len() as u32 - 1, 8);
+        for book in &self.books {
+            book.write_header(&mut header);
+        }
+        header.write(0, 6); // One time-domain transform, which must be zero
+        header.write(0, 16);
+
+        header.write(0, 6); // One floor, of type 1
+        header.write(1, 16);
+        header.write(FLOOR_PARTITIONS as u32, 5);
+        for _ in 0..FLOOR_PARTITIONS {
+            header.write(0, 4); // Every partition is of class 0
+        }
+        header.write(FLOOR_CLASS_DIMENSIONS as u32 - 1, 3);
+        header.write(0, 2); // No subclasses, so every value is coded with the one book
+        header.write(FLOOR_BOOK as u32 + 1, 8);
+        header.write(FLOOR_MULTIPLIER as u32 - 1, 2);
+        header.write(FLOOR_RANGE_BITS, 4);
+        for &x in &self.floor_x[2..] {
+            header.write(x as u32, FLOOR_RANGE_BITS);
+        }
+
+        header.write(0, 6); // One residue, of type 1
+        header.write(1, 16);
+        header.write(0, 24);
+        header.write(HALF as u32, 24);
+        header.write(PARTITION_SIZE as u32 - 1, 24);
+        header.write(CLASSIFICATIONS as u32 - 1, 6);
+        header.write(CLASS_BOOK as u32, 8);
+        // Silent partitions code nothing; small ones one pass; large ones two.
+        for cascade in [0b00, 0b01, 0b11] {
+            header.write(cascade, 3);
+            header.write(0, 1);
+        }
+        for book in [SMALL_BOOK, COARSE_BOOK, FINE_BOOK] {
+            header.write(book as u32, 8);
+        }
+
+        header.write(0, 6); // One mapping, of type 0, with one submap and no coupling
+        header.write(0, 16);
+        header.write(0, 1);
+        header.write(0, 1);
+        header.write(0, 2);
+        header.write(0, 8);
+        header.write(0, 8);
+        header.write(0, 8);
+
+        header.write(0, 6); // One mode: long blocks, using the mapping
+        header.write(0, 1);
+        header.write(0, 16);
+        header.write(0, 16);
+        header.write(0, 8);
+        header.write(1, 1); // Framing bit
+        header.bytes
+    }
+
+    /// Codes one block, given as `BLOCK` samples of every channel.
+    fn audio_packet(&self, block: &[Vec<f64>]) -> Vec<u8> {
+        let mut packet = BitPacker::new();
+        packet.write(0, 1); // Audio packet. With a single mode, no mode number follows
+        let residues: Vec<Option<Vec<i32>>> = block
+            .iter()
+            .map(|samples| {
+                let windowed: Vec<f64> =
+                    samples.iter().zip(&self.window).map(|(sample, w)| sample * w).collect();
+                let spectrum = self.mdct.forward(&windowed);
+                let floor = self.encode_floor(&spectrum, &mut packet)?;
+                Some(
+                    spectrum
+                        .iter()
+                        .zip(&floor)
+                        .map(|(x, f)| ((x / f).round() as i32).clamp(-MAX_RESIDUE, MAX_RESIDUE))
+                        .collect(),
+                )
+            })
+            .collect();
+        self.encode_residue(&residues, &mut packet);
+        packet.bytes
+    }
+
+    /// Codes the floor of one channel and returns its curve as the decoder will render it, or
+    /// codes the channel as silent and returns `None` if no line would survive quantisation.
+    fn encode_floor(&self, spectrum: &[f64], packet: &mut BitPacker) -> Option<Vec<f64>> {
+        let peak = spectrum.iter().fold(0.0f64, |peak, x| peak.max(x.abs()));
+        if peak < self.min_step / 2.0 {
+            packet.write(0, 1);
+            return None;
+        }
+        packet.write(1, 1);
+
+        // The step wanted at each point: a fraction of the peak between its neighbours.
+        let mut sorted: Vec<usize> = self.floor_x.clone();
+        sorted.sort_unstable();
+        let targets: Vec<i32> = self
+            .floor_x
+            .iter()
+            .map(|&x| {
+                let position = sorted.binary_search(&x).unwrap();
+                let from = sorted[position.saturating_sub(1)];
+                let to = sorted.get(position + 1).copied().unwrap_or(HALF).min(HALF);
+                let local = spectrum[from..to.max(from + 1)]
+                    .iter()
+                    .fold(0.0f64, |peak, x| peak.max(x.abs()));
+                let step = (local / self.peak_steps).max(self.min_step);
+                let index = 255.0 + step.ln() / FLOOR_DB_STEP.ln();
+                ((index / FLOOR_MULTIPLIER as f64).floor() as i32).clamp(0, FLOOR_RANGE - 1)
+            })
+            .collect();
+
+        // Mirror the decoder's amplitude synthesis to find the value coded at each point.
+        let count = self.floor_x.len();
+        let mut final_y = vec![0; count];
+        let mut used = vec![false; count];
+        final_y[0] = targets[0];
+        final_y[1] = targets[1];
+        used[0] = true;
+        used[1] = true;
+        let mut values = Vec::with_capacity(count - 2);
+        for i in 2..count {
+            let x = self.floor_x[i];
+            let earlier = || (0..i).map(|j| (self.floor_x[j], j));
+            let (_, low) = earlier().filter(|&(other, _)| other < x).max().unwrap();
+            let (_, high) = earlier().filter(|&(other, _)| other > x).min().unwrap();
+            let predicted = render_point(
+                self.floor_x[low], final_y[low], self.floor_x[high], final_y[high], x);
+            let target = targets[i];
+            if (target - predicted).abs() <= FLOOR_TOLERANCE {
+                final_y[i] = predicted;
+                values.push(0);
+            } else {
+                final_y[i] = target;
+                used[low] = true;
+                used[high] = true;
+                used[i] = true;
+                values.push(floor_value(target, predicted));
+            }
+        }
+
+        packet.write(final_y[0] as u32, 7);
+        packet.write(final_y[1] as u32, 7);
+        for value in values {
+            self.books[FLOOR_BOOK].write_entry(packet, value as usize);
+        }
+
+        // Render the curve between the points in use, as the decoder does.
+        let mut order: Vec<usize> = (0..count).collect();
+        order.sort_by_key(|&i| self.floor_x[i]);
+        let mut curve = vec![0; HALF];
+        let (mut lx, mut ly) = (0, final_y[order[0]] * FLOOR_MULTIPLIER as i32);
+        for &i in &order[1..] {
+            if used[i] {
+                let (hx, hy) = (self.floor_x[i], final_y[i] * FLOOR_MULTIPLIER as i32);
+                render_line(lx, ly, hx, hy, &mut curve);
+                lx = hx;
+                ly = hy;
+            }
+        }
+        Some(curve.iter().map(|&index| FLOOR_DB_STEP.powi(index - 255)).collect())
+    }
+
+    /// Codes the quantised spectra of every channel in use with residue type 1.
+    fn encode_residue(&self, residues: &[Option<Vec<i32>>], packet: &mut BitPacker) {
+        let partitions = HALF / PARTITION_SIZE;
+        let classes: Vec<Option<Vec<usize>>> = residues
+            .iter()
+            .map(|residue| {
+                residue.as_ref().map(|residue| {
+                    residue
+                        .chunks(PARTITION_SIZE)
+                        .map(|partition| match partition.iter().map(|q| q.abs()).max() {
+                            Some(0) | None => 0,
+                            Some(1..=2) => 1,
+                            _ => 2,
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+        let per_word = self.books[CLASS_BOOK].dimensions;
+
+        // Passes beyond the second have no books, so they code nothing.
+        for pass in 0..2 {
+            let mut partition = 0;
+            while partition < partitions {
+                if pass == 0 {
+                    for classes in classes.iter().flatten() {
+                        let entry = (partition..partition + per_word).fold(0, |entry, i| {
+                            entry * CLASSIFICATIONS + classes.get(i).copied().unwrap_or(0)
+                        });
+                        self.books[CLASS_BOOK].write_entry(packet, entry);
+                    }
+                }
+                for _ in 0..per_word {
+                    if partition == partitions {
+                        break;
+                    }
+                    for (residue, classes) in residues.iter().zip(&classes) {
+                        let (Some(residue), Some(classes)) = (residue, classes) else { continue };
+                        let values = &residue[partition * PARTITION_SIZE..][..PARTITION_SIZE];
+                        let coded: Vec<i32> = match (classes[partition], pass) {
+                            (1, 0) => values.to_vec(),
+                            (2, 0) => values.iter().map(|q| coarse(*q) * COARSE_STEP).collect(),
+                            (2, 1) => values.iter().map(|q| q - coarse(*q) * COARSE_STEP).collect(),
+                            _ => continue,
+                        };
+                        let book = match (classes[partition], pass) {
+                            (1, _) => SMALL_BOOK,
+                            (_, 0) => COARSE_BOOK,
+                            _ => FINE_BOOK,
+                        };
+                        let book = &self.books[book];
+                        for vector in coded.chunks(book.dimensions) {
+                            book.write_entry(packet, book.entry_of(vector));
+                        }
+                    }
+                    partition += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The coarse multiple of a large residue value.
+fn coarse(value: i32) -> i32 {
+    (value as f64 / COARSE_STEP as f64).round() as i32
+}
+
+/// The floor 1 X positions, in coding order: the two ends, and then points spaced evenly on a
+/// logarithmic frequency scale, coded coarsest first so that each is predicted from close
+/// neighbours.
+fn floor_points() -> Vec<usize> {
+    let extra = FLOOR_PARTITIONS * FLOOR_CLASS_DIMENSIONS;
+    let mut points = Vec::with_capacity(extra);
+    for i in 0..extra {
+        let x = (2.0 * (HALF as f64 / 2.0).powf(i as f64 / extra as f64)).round() as usize;
+        points.push(x.max(points.last().map_or(0, |&previous| previous + 1)));
+    }
+    let bits = extra.trailing_zeros();
+    let mut order: Vec<usize> = (0..extra).collect();
+    order.sort_by_key(|&i| i.reverse_bits() >> (usize::BITS - bits));
+    let mut floor_x = vec![0, HALF];
+    floor_x.extend(order.into_iter().map(|i| points[i]));
+    floor_x
+}
+
+/// The value that makes the decoder place a floor point at `target` when the line through its
+/// neighbours predicts `predicted`.
+fn floor_value(target: i32, predicted: i32) -> i32 {
+    let high_room = FLOOR_RANGE - predicted;
+    let low_room = predicted;
+    let room = 2 * high_room.min(low_room);
+    let difference = target - predicted;
+    if difference > 0 && 2 * difference < room {
+        2 * difference
+    } else if difference < 0 && -2 * difference - 1 < room {
+        -2 * difference - 1
+    } else if high_room > low_room {
+        difference + low_room
+    } else {
+        high_room - 1 - difference
+    }
+}
+
+fn render_point(x0: usize, y0: i32, x1: usize, y1: i32, x: usize) -> i32 {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let offset = dy.abs() * (x - x0) as i32 / adx;
+    if dy < 0 { y0 - offset } else { y0 + offset }
+}
+
+/// Draws the integer line the decoder draws from (x0, y0) up to, but not including, x1.
+fn render_line(x0: usize, y0: i32, x1: usize, y1: i32, curve: &mut [i32]) {
+    let dy = y1 - y0;
+    let adx = (x1 - x0) as i32;
+    let base = dy / adx;
+    let step = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0;
+    let mut error = 0;
+    curve[x0] = y;
+    for value in &mut curve[x0 + 1..x1] {
+        error += ady;
+        if error >= adx {
+            error -= adx;
+            y += step;
+        } else {
+            y += base;
+        }
+        *value = y;
+    }
+}
+
+/// A Vorbis codebook: Huffman codewords for its entries and, for vector books, the grid of
+/// integer values they stand for.
+struct Codebook {
+    dimensions: usize,
+    lengths: Vec<u8>,
+    codewords: Vec<u32>,
+    /// The smallest value, the step between values and how many values each dimension takes.
+    grid: Option<(i32, i32, usize)>,
+}
+
+impl Codebook {
+    /// A book whose codeword lengths suit entries occurring with the given relative weights.
+    fn new(dimensions: usize, weights: &[f64], grid: Option<(i32, i32, usize)>) -> Self {
+        let lengths = huffman_lengths(weights);
+        let codewords = codewords(&lengths);
+        Codebook { dimensions, lengths, codewords, grid }
+    }
+
+    /// A two-dimensional book of every pair of `count` values from `minimum` in steps of `step`,
+    /// where pairs further from zero are rarer by `falloff` per step.
+    fn grid(minimum: i32, step: i32, count: usize, falloff: f64) -> Self {
+        let weights: Vec<f64> = (0..count * count)
+            .map(|entry| {
+                let distance = [entry % count, entry / count]
+                    .iter()
+                    .map(|&i| (minimum + i as i32 * step).abs() / step)
+                    .sum::<i32>();
+                // Bounded so that no codeword needs more than 32 bits.
+                (-falloff * distance as f64).exp().max(1e-7)
+            })
+            .collect();
+        Codebook::new(2, &weights, Some((minimum, step, count)))
+    }
+
+    fn write_header(&self, packer: &mut BitPacker) {
+        packer.write(0x564342, 24);
+        packer.write(self.dimensions as u32, 16);
+        packer.write(self.lengths.len() as u32, 24);
+        packer.write(0, 1); // Not ordered
+        packer.write(0, 1); // Not sparse
+        for &length in &self.lengths {
+            packer.write(length as u32 - 1, 5);
+        }
+        match self.grid {
+            None => packer.write(0, 4),
+            Some((minimum, step, count)) => {
+                let value_bits = usize::BITS - (count - 1).leading_zeros();
+                packer.write(1, 4);
+                packer.write(pack_float(minimum), 32);
+                packer.write(pack_float(step), 32);
+                packer.write(value_bits - 1, 4);
+                packer.write(0, 1); // Values are not cumulative
+                for multiplicand in 0..count {
+                    packer.write(multiplicand as u32, value_bits);
+                }
+            }
+        }
+    }
+
+    /// Writes an entry's codeword, first bit first.
+    fn write_entry(&self, packer: &mut BitPacker, entry: usize) {
+        let (codeword, length) = (self.codewords[entry], self.lengths[entry] as u32);
+        for bit in (0..length).rev() {
+            packer.write(codeword >> bit & 1, 1);
+        }
+    }
+
+    /// The entry of a vector book standing for `vector`, whose values must lie on its grid.
+    fn entry_of(&self, vector: &[i32]) -> usize {
+        let (minimum, step, count) = self.grid.expect("scalar books have no vectors");
+        vector
+            .iter()
+            .rev()
+            .fold(0, |entry, value| entry * count + ((value - minimum) / step) as usize)
+    }
+}
+
+/// Codeword lengths of a Huffman code for the weights.
+fn huffman_lengths(weights: &[f64]) -> Vec<u8> {
+    let mut lengths = vec![0u8; weights.len()];
+    let mut trees: Vec<(f64, Vec<usize>)> =
+        weights.iter().enumerate().map(|(entry, &weight)| (weight, vec![entry])).collect();
+    while trees.len() > 1 {
+        trees.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let (lightest, mut first) = trees.pop().unwrap();
+        let (next, second) = trees.pop().unwrap();
+        first.extend(second);
+        for &entry in &first {
+            lengths[entry] += 1;
+        }
+        trees.push((lightest + next, first));
+    }
+    assert!(lengths.iter().all(|&length| (1..=32).contains(&length)));
+    lengths
+}
+
+/// Assigns codewords to lengths in entry order, as the decoder does: each entry takes the
+/// lowest codeword of its length that does not extend an earlier one.
+fn codewords(lengths: &[u8]) -> Vec<u32> {
+    let mut marker = [0u32; 33];
+    lengths
+        .iter()
+        .map(|&length| {
+            let length = length as usize;
+            let mut entry = marker[length];
+            let codeword = entry;
+            for j in (1..=length).rev() {
+                if marker[j] & 1 == 1 {
+                    marker[j] = if j == 1 { marker[1] + 1 } else { marker[j - 1] << 1 };
+                    break;
+                }
+                marker[j] += 1;
+            }
+            for j in length + 1..33 {
+                if marker[j] >> 1 != entry {
+                    break;
+                }
+                entry = marker[j];
+                marker[j] = marker[j - 1] << 1;
+            }
+            codeword
+        })
+        .collect()
+}
+
+/// Packs an integer in Vorbis's float format: a 21-bit mantissa, a biased exponent and a sign.
+fn pack_float(value: i32) -> u32 {
+    let sign = if value < 0 { 0x8000_0000 } else { 0 };
+    sign | 788 << 21 | value.unsigned_abs()
+}
+
+/// Packs values least significant bit first, as Vorbis packets are read.
+struct BitPacker {
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl BitPacker {
+    fn new() -> Self {
+        BitPacker { bytes: Vec::new(), used: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        for bit in 0..count {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= ((value >> bit & 1) as u8) << self.used;
+            self.used = (self.used + 1) % 8;
+        }
+    }
+}
+
+/// The forward MDCT of `2 * lines` samples, computed as a DCT-IV through a complex FFT of
+/// `lines / 2` points and scaled so that the decoder's inverse transform restores the input.
+struct Mdct {
+    lines: usize,
+    pre_twiddles: Vec<(f64, f64)>,
+    post_twiddles: Vec<(f64, f64)>,
+    fft: Fft,
+}
+
+impl Mdct {
+    fn new(lines: usize) -> Self {
+        let twiddles = |offset: f64| {
+            (0..lines / 2)
+                .map(|n| {
+                    let angle = -PI * (n as f64 + offset) / lines as f64;
+                    (angle.cos(), angle.sin())
+                })
+                .collect()
+        };
+        Mdct {
+            lines,
+            pre_twiddles: twiddles(0.25),
+            post_twiddles: twiddles(0.0),
+            fft: Fft::new(lines / 2),
+        }
+    }
+
+    fn forward(&self, input: &[f64]) -> Vec<f64> {
+        let m = self.lines;
+        // Fold the four quarters (a, b, c, d) into (-c reversed - d, a - b reversed).
+        let folded: Vec<f64> = (0..m)
+            .map(|n| {
+                if n < m / 2 {
+                    -input[3 * m / 2 - 1 - n] - input[3 * m / 2 + n]
+                } else {
+                    input[n - m / 2] - input[3 * m / 2 - 1 - n]
+                }
+            })
+            .collect();
+
+        let mut data: Vec<(f64, f64)> = (0..m / 2)
+            .map(|n| complex_mul((folded[2 * n], folded[m - 1 - 2 * n]), self.pre_twiddles[n]))
+            .collect();
+        self.fft.transform(&mut data);
+
+        let scale = 2.0 / m as f64;
+        let mut output = vec![0.0; m];
+        for (k, &value) in data.iter().enumerate() {
+            let (re, im) = complex_mul(value, self.post_twiddles[k]);
+            output[2 * k] = re * scale;
+            output[m - 1 - 2 * k] = -im * scale;
+        }
+        output
+    }
+}
+
+fn complex_mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+/// An iterative radix-2 FFT of a fixed power-of-two size.
+struct Fft {
+    size: usize,
+    roots: Vec<(f64, f64)>,
+}
+
+impl Fft {
+    fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+        let roots = (0..size / 2)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f64 / size as f64;
+                (angle.cos(), angle.sin())
+            })
+            .collect();
+        Fft { size, roots }
+    }
+
+    fn transform(&self, data: &mut [(f64, f64)]) {
+        let bits = self.size.trailing_zeros();
+        for i in 0..self.size {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+        let mut length = 2;
+        while length <= self.size {
+            let stride = self.size / length;
+            for start in (0..self.size).step_by(length) {
+                for k in 0..length / 2 {
+                    let odd = complex_mul(data[start + k + length / 2], self.roots[k * stride]);
+                    let even = data[start + k];
+                    data[start + k] = (even.0 + odd.0, even.1 + odd.1);
+                    data[start + k + length / 2] = (even.0 - odd.0, even.1 - odd.1);
+                }
+            }
+            length *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::TAU;
+    use std::io::Cursor;
+    use lewton::inside_ogg::OggStreamReader;
+
+    const RATE: u32 = 44_100;
+
+    /// Tones with a little pseudo-random noise, different in every channel.
+    fn test_signal(channels: u16, frames: usize) -> Vec<f32> {
+        (0..frames * channels as usize)
+            .map(|i| {
+                let (frame, channel) = (i / channels as usize, i % channels as usize);
+                let t = frame as f32 / RATE as f32;
+                let pitch = 440.0 * (1.0 + channel as f32 / 2.0);
+                0.3 * (TAU * pitch * t).sin()
+                    + 0.2 * (TAU * 3170.0 * t).sin() * (TAU * 3.0 * t).cos()
+                    + 0.05 * (((i * 7919) % 1009) as f32 / 504.0 - 1.0)
+            })
+            .collect()
+    }
+
+    /// Decodes with an independent decoder, returning the channel count, the sample rate and
+    /// the interleaved samples.
+    fn decode(bytes: Vec<u8>) -> (u16, u32, Vec<f32>) {
+        let mut reader = OggStreamReader::new(Cursor::new(bytes)).unwrap();
+        let mut samples = Vec::new();
+        while let Some(packet) = reader.read_dec_packet_itl().unwrap() {
+            samples.extend(packet.into_iter().map(|sample| sample as f32 / 32768.0));
+        }
+        (reader.ident_hdr.audio_channels as u16, reader.ident_hdr.audio_sample_rate, samples)
+    }
+
+    fn signal_to_noise(original: &[f32], decoded: &[f32]) -> f32 {
+        let signal: f32 = original.iter().map(|a| a * a).sum();
+        let noise: f32 = original.iter().zip(decoded).map(|(a, b)| (a - b).powi(2)).sum();
+        10.0 * (signal / noise).log10()
+    }
+
+    #[test]
+    fn mdct_matches_the_direct_transform() {
+        let input: Vec<f64> = (0..BLOCK).map(|i| ((i * 7919) % 101) as f64 / 50.0 - 1.0).collect();
+        let fast = Mdct::new(HALF).forward(&input);
+        for (k, &value) in fast.iter().enumerate() {
+            let direct: f64 = input
+                .iter()
+                .enumerate()
+                .map(|(n, x)| {
+                    let phase = PI / HALF as f64 * (n as f64 + 0.5 + HALF as f64 / 2.0);
+                    x * (phase * (k as f64 + 0.5)).cos()
+                })
+                .sum();
+            assert!((value - direct * 2.0 / HALF as f64).abs() < 1e-9, "line {}", k);
+        }
+    }
+
+    #[test]
+    fn decodes_to_the_original_signal() {
+        for channels in [1, 2] {
+            // Not a whole number of blocks, so the end of the last one is trimmed.
+            let samples = test_signal(channels, RATE as usize + 777);
+            let bytes = encode_ogg_vorbis(&samples, channels, RATE, 5).unwrap();
+            let pcm_bytes = samples.len() * 2;
+            assert!(bytes.len() * 4 < pcm_bytes, "{} bytes for {} of PCM", bytes.len(), pcm_bytes);
+
+            let (decoded_channels, rate, decoded) = decode(bytes);
+            assert_eq!((decoded_channels, rate), (channels, RATE));
+            assert_eq!(decoded.len(), samples.len());
+            let snr = signal_to_noise(&samples, &decoded);
+            assert!(snr > 25.0, "{} channels decoded at {:.1} dB", channels, snr);
+        }
+    }
+
+    #[test]
+    fn quality_trades_size_for_fidelity() {
+        let samples = test_signal(1, RATE as usize / 2);
+        let results: Vec<(usize, f32)> = [0, 5, 10]
+            .into_iter()
+            .map(|quality| {
+                let bytes = encode_ogg_vorbis(&samples, 1, RATE, quality).unwrap();
+                let size = bytes.len();
+                (size, signal_to_noise(&samples, &decode(bytes).2))
+            })
+            .collect();
+        for pair in results.windows(2) {
+            assert!(pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1, "{:?}", results);
+        }
+    }
+
+    #[test]
+    fn silence_and_empty_input_decode() {
+        let (_, _, decoded) = decode(encode_ogg_vorbis(&[], 2, RATE, 5).unwrap());
+        assert!(decoded.is_empty());
+
+        for frames in [300, 5000] {
+            let bytes = encode_ogg_vorbis(&vec![0.0; frames], 1, RATE, 5).unwrap();
+            let (_, _, decoded) = decode(bytes);
+            assert_eq!(decoded, vec![0.0; frames]);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_settings() {
+        assert!(encode_ogg_vorbis(&[], 0, RATE, 5).is_err());
+        assert!(encode_ogg_vorbis(&[], 256, RATE, 5).is_err());
+        assert!(encode_ogg_vorbis(&[], 1, 0, 5).is_err());
+        assert!(encode_ogg_vorbis(&[], 1, RATE, 11).is_err());
+    }
+}